@@ -0,0 +1,89 @@
+//! Update and lookup latency of the device store as the number of advertised devices grows.
+//! Run with `cargo bench`. Lookups should stay roughly flat across sizes. Updates grow linearly:
+//! they copy the device map, which a ListAndWatch stream always holds, and include the resend of
+//! the full device list, as the v1beta1 API has no delta updates.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::hint::black_box;
+
+#[allow(dead_code)]
+mod k8s {
+    tonic::include_proto!("v1beta1");
+}
+
+#[path = "../src/store.rs"]
+#[allow(dead_code)]
+mod store;
+
+const SIZES: &[usize] = &[16, 1024, 16384];
+
+fn devices(count: usize) -> store::Devices {
+    (0..count)
+        .map(|idx| {
+            let id = format!("nvidia.com/gpu={idx}");
+            let dev = k8s::Device {
+                id: id.clone(),
+                health: "Healthy".to_string(),
+                topology: Some(k8s::TopologyInfo {
+                    nodes: vec![k8s::NumaNode {
+                        id: (idx % 2) as i64,
+                    }],
+                }),
+            };
+            (id, dev)
+        })
+        .collect()
+}
+
+fn toggle_health(store: &store::DeviceStore, id: &str) {
+    store.update(|devs| match devs.get_mut(id) {
+        Some(dev) => {
+            dev.health = if dev.health == "Healthy" {
+                "Unhealthy".to_string()
+            } else {
+                "Healthy".to_string()
+            };
+            true
+        }
+        None => false,
+    });
+}
+
+// A health change as kubelet sees it: the update itself plus what a ListAndWatch stream does to
+// re-send the new generation.
+fn bench_update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update_and_resend");
+    for &size in SIZES {
+        let store = store::DeviceStore::new(devices(size));
+        let mut updates = store.subscribe();
+        let id = format!("nvidia.com/gpu={}", size / 2);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &id, |b, id| {
+            b.iter(|| {
+                toggle_health(&store, id);
+                let snapshot = updates.borrow_and_update().clone();
+                black_box(snapshot.list_and_watch_response().clone())
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("allocate_lookup");
+    for &size in SIZES {
+        let store = store::DeviceStore::new(devices(size));
+        let ids: Vec<String> = (0..8)
+            .map(|idx| format!("nvidia.com/gpu={}", idx * size / 8))
+            .collect();
+        group.bench_with_input(BenchmarkId::from_parameter(size), &ids, |b, ids| {
+            b.iter(|| {
+                let snapshot = store.snapshot();
+                ids.iter().all(|id| snapshot.contains(black_box(id)))
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_update, bench_lookup);
+criterion_main!(benches);
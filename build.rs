@@ -8,8 +8,20 @@ fn main() -> Result<(), Box<dyn ::std::error::Error>> {
     tonic_prost_build::configure()
         .build_client(true)
         .build_server(true)
-        .compile_with_config(config, &["proto/api.proto"], &["proto"])?;
+        .compile_with_config(
+            config,
+            &[
+                "proto/api.proto",
+                "proto/podresources.proto",
+                "proto/admin.proto",
+                "proto/devicemap.proto",
+            ],
+            &["proto"],
+        )?;
 
     println!("cargo:rerun-if-changed=proto/api.proto");
+    println!("cargo:rerun-if-changed=proto/podresources.proto");
+    println!("cargo:rerun-if-changed=proto/admin.proto");
+    println!("cargo:rerun-if-changed=proto/devicemap.proto");
     Ok(())
 }
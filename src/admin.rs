@@ -0,0 +1,295 @@
+use crate::{
+    peercred::PeerPolicy,
+    retirement::{self, Retirements},
+    rpc::RpcLimits,
+    socket, unix_channel,
+};
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{sync::Notify, task::JoinHandle, time::sleep};
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::{async_trait, Request, Response, Status};
+
+pub mod api {
+    tonic::include_proto!("admin.v1");
+}
+
+pub const ADMIN_SOCKET_NAME: &str = "admin.sock";
+pub const HANDOFF_TOKEN_FILE_NAME: &str = "handoff.token";
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl From<retirement::Retirement> for api::Retirement {
+    fn from(entry: retirement::Retirement) -> Self {
+        let state = match entry.state {
+            retirement::RetirementState::Draining => api::RetirementState::Draining,
+            retirement::RetirementState::Retired => api::RetirementState::Retired,
+        };
+        api::Retirement {
+            resource_name: entry.resource_name,
+            device_id: entry.device_id,
+            state: state as i32,
+            pods: entry.pods,
+        }
+    }
+}
+
+/// A random secret a new instance has to present to make us hand off. Peer credentials alone
+/// would let anything running as an allowed user stop the plugin.
+pub fn new_handoff_token() -> anyhow::Result<String> {
+    let mut bytes = [0u8; 32];
+    let len = unsafe { libc::getrandom(bytes.as_mut_ptr().cast(), bytes.len(), 0) };
+    if len != bytes.len() as isize {
+        anyhow::bail!("getrandom failed: {}", io::Error::last_os_error());
+    }
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/// Stores `token` at `path`, readable only by our own user.
+pub fn write_handoff_token(path: &Path, token: &str) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let _ = fs::remove_file(&tmp);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)
+        .map_err(|err| anyhow::anyhow!("failed to create {}: {err}", tmp.display()))?;
+    file.write_all(token.as_bytes())?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+// Compares in constant time, so that response times do not tell how much of a guess was right.
+fn tokens_match(given: &str, expected: &str) -> bool {
+    let (given, expected) = (given.as_bytes(), expected.as_bytes());
+    let diff = given
+        .iter()
+        .zip(expected)
+        .fold(0, |diff, (a, b)| diff | (a ^ b));
+    given.len() == expected.len() && std::hint::black_box(diff) == 0
+}
+
+pub struct AdminService {
+    retirements: Arc<Retirements>,
+    handoff: Arc<Notify>,
+    token: String,
+}
+
+impl AdminService {
+    /// `handoff` is notified when a newly started instance presenting `token` asks us to stop.
+    pub fn new(retirements: Arc<Retirements>, handoff: Arc<Notify>, token: String) -> Self {
+        Self {
+            retirements,
+            handoff,
+            token,
+        }
+    }
+}
+
+#[async_trait]
+impl api::admin_server::Admin for AdminService {
+    async fn retire_device(
+        &self,
+        request: Request<api::RetireDeviceRequest>,
+    ) -> Result<Response<api::Retirement>, Status> {
+        let req = request.into_inner();
+        let entry = self
+            .retirements
+            .retire(&req.resource_name, &req.device_id)
+            .map_err(|err| Status::failed_precondition(err.to_string()))?;
+        Ok(Response::new(entry.into()))
+    }
+
+    async fn list_retirements(
+        &self,
+        _request: Request<api::ListRetirementsRequest>,
+    ) -> Result<Response<api::ListRetirementsResponse>, Status> {
+        Ok(Response::new(api::ListRetirementsResponse {
+            retirements: self
+                .retirements
+                .list()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }))
+    }
+
+    async fn handoff(
+        &self,
+        request: Request<api::HandoffRequest>,
+    ) -> Result<Response<api::HandoffResponse>, Status> {
+        let req = request.into_inner();
+        if !tokens_match(&req.token, &self.token) {
+            eprintln!("rejecting handoff from pid {}: wrong token", req.pid);
+            return Err(Status::permission_denied("wrong handoff token"));
+        }
+        println!("instance with pid {} is taking over, stopping", req.pid);
+        self.handoff.notify_one();
+        Ok(Response::new(api::HandoffResponse {}))
+    }
+}
+
+pub fn start_admin_server(
+    socket_path: PathBuf,
+    service: AdminService,
+    peers: PeerPolicy,
+    limits: RpcLimits,
+    force: bool,
+) -> anyhow::Result<JoinHandle<()>> {
+    if let Some(dir) = socket_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let uds = socket::bind(&socket_path, force)?;
+    let incoming = UnixListenerStream::new(uds);
+    let service = api::admin_server::AdminServer::with_interceptor(service, peers);
+
+    Ok(tokio::spawn(async move {
+        if let Err(err) = limits
+            .server()
+            .add_service(service)
+            .serve_with_incoming(incoming)
+            .await
+        {
+            eprintln!("admin gRPC server crashed: {err}");
+        }
+    }))
+}
+
+pub async fn client(
+    socket_path: PathBuf,
+) -> anyhow::Result<api::admin_client::AdminClient<tonic::transport::Channel>> {
+    let channel = unix_channel(socket_path.clone())
+        .await
+        .map_err(|err| anyhow::anyhow!("failed to connect to {}: {err}", socket_path.display()))?;
+    Ok(api::admin_client::AdminClient::new(channel))
+}
+
+fn format_retirement(entry: &api::Retirement) -> String {
+    let state = match api::RetirementState::try_from(entry.state) {
+        Ok(api::RetirementState::Draining) => "draining",
+        Ok(api::RetirementState::Retired) => "retired",
+        Err(_) => "unknown",
+    };
+    let mut line = format!(
+        "{:<32} {:<24} {state}",
+        entry.device_id, entry.resource_name
+    );
+    if !entry.pods.is_empty() {
+        line.push_str(&format!(" (in use by {})", entry.pods.join(", ")));
+    }
+    line
+}
+
+pub async fn retire(
+    socket_path: PathBuf,
+    resource_name: &str,
+    device_id: &str,
+) -> anyhow::Result<()> {
+    let mut client = client(socket_path).await?;
+    let entry = client
+        .retire_device(api::RetireDeviceRequest {
+            resource_name: resource_name.to_string(),
+            device_id: device_id.to_string(),
+        })
+        .await?
+        .into_inner();
+    println!("{}", format_retirement(&entry));
+    Ok(())
+}
+
+pub async fn list_retirements(socket_path: PathBuf) -> anyhow::Result<()> {
+    let mut client = client(socket_path).await?;
+    let resp = client
+        .list_retirements(api::ListRetirementsRequest {})
+        .await?
+        .into_inner();
+    if resp.retirements.is_empty() {
+        println!("no devices marked for retirement");
+    }
+    for entry in &resp.retirements {
+        println!("{}", format_retirement(entry));
+    }
+    Ok(())
+}
+
+/// Asks the instance serving `socket_path` to stop, proving who we are with the token it stored
+/// at `token_file`, then waits until it has released the socket.
+// Whether an admin server still answers on `socket_path`. Any reply counts, even a refusal.
+async fn answers(socket_path: &Path) -> bool {
+    let probe = async {
+        let Ok(mut client) = client(socket_path.to_path_buf()).await else {
+            return false;
+        };
+        match client
+            .list_retirements(api::ListRetirementsRequest {})
+            .await
+        {
+            Ok(_) => true,
+            Err(status) => status.code() != tonic::Code::Unavailable,
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(1), probe)
+        .await
+        .unwrap_or(false)
+}
+
+pub async fn hand_off(socket_path: PathBuf, token_file: &Path) -> anyhow::Result<()> {
+    let token = fs::read_to_string(token_file)
+        .map_err(|err| anyhow::anyhow!("failed to read {}: {err}", token_file.display()))?;
+    let mut client = client(socket_path.clone()).await?;
+    client
+        .handoff(api::HandoffRequest {
+            pid: std::process::id(),
+            token,
+        })
+        .await?;
+
+    // systemd keeps listening on a socket-activated admin socket after the previous instance
+    // stopped serving it, so there only an unanswered request tells.
+    let inherited = socket::is_inherited(&socket_path);
+    let deadline = Instant::now() + HANDOFF_TIMEOUT;
+    loop {
+        let serving = if inherited {
+            answers(&socket_path).await
+        } else {
+            socket::is_live(&socket_path)
+        };
+        if !serving {
+            break;
+        }
+        if Instant::now() >= deadline {
+            anyhow::bail!(
+                "previous instance did not release {} in time",
+                socket_path.display()
+            );
+        }
+        sleep(Duration::from_millis(200)).await;
+    }
+    println!("previous instance has stopped");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_tokens() {
+        let cases = [
+            ("abc123", "abc123", true),
+            ("abc124", "abc123", false),
+            ("abc12", "abc123", false),
+            ("abc1234", "abc123", false),
+            ("", "abc123", false),
+        ];
+        for (given, expected, want) in cases {
+            assert_eq!(tokens_match(given, expected), want, "{given:?}");
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fs::{self, File},
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Substrings of keys whose values are dropped from everything in the bundle.
+const SENSITIVE_KEYS: &[&str] = &[
+    "token",
+    "secret",
+    "password",
+    "passwd",
+    "credential",
+    "authorization",
+    "private",
+    "serial",
+];
+const REDACTED: &str = "<redacted>";
+/// Most recent Xid lines kept from the kernel log.
+const MAX_XID_LINES: usize = 200;
+
+// Replaces whole-word occurrences of `value`, so that a short hostname does not mangle words that
+// merely contain it.
+fn replace_word(text: &str, value: &str, placeholder: &str) -> String {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut prev = None;
+    while let Some(pos) = rest.find(value) {
+        let end = pos + value.len();
+        let before = rest[..pos].chars().next_back().or(prev);
+        let after = rest[end..].chars().next();
+        out.push_str(&rest[..pos]);
+        if before.is_some_and(is_word) || after.is_some_and(is_word) {
+            out.push_str(value);
+        } else {
+            out.push_str(placeholder);
+        }
+        prev = value.chars().next_back();
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// Whether `text` starts with a UUID such as 6b6b3c0e-0f3a-4d6c-9a5e-2f0a1b2c3d4e.
+fn uuid_at(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    bytes.len() >= 36
+        && bytes[..36].iter().enumerate().all(|(idx, byte)| match idx {
+            8 | 13 | 18 | 23 => *byte == b'-',
+            _ => byte.is_ascii_hexdigit(),
+        })
+        && !bytes.get(36).is_some_and(u8::is_ascii_alphanumeric)
+}
+
+// Replaces UUIDs (GPU and MIG UUIDs, pod UIDs) with numbered placeholders, so that one ID can
+// still be followed through a file.
+fn redact_uuids(text: &str) -> String {
+    let mut seen: Vec<&str> = Vec::new();
+    let mut out = String::with_capacity(text.len());
+    let mut idx = 0;
+    while let Some(c) = text[idx..].chars().next() {
+        let at_boundary = !text[..idx]
+            .chars()
+            .next_back()
+            .is_some_and(|prev| prev.is_ascii_alphanumeric());
+        if at_boundary && uuid_at(&text[idx..]) {
+            let uuid = &text[idx..idx + 36];
+            let n = match seen
+                .iter()
+                .position(|known| known.eq_ignore_ascii_case(uuid))
+            {
+                Some(n) => n,
+                None => {
+                    seen.push(uuid);
+                    seen.len() - 1
+                }
+            };
+            out.push_str(&format!("<uuid-{}>", n + 1));
+            idx += 36;
+        } else {
+            out.push(c);
+            idx += c.len_utf8();
+        }
+    }
+    out
+}
+
+/// Hides values that should not end up in a bug report: anything keyed like a credential or a
+/// hardware serial number, UUIDs, and the words in `replacements` (e.g. the node name).
+pub fn redact(text: &str, replacements: &[(String, &str)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    for line in text.lines() {
+        let lower = line.to_ascii_lowercase();
+        let split = line
+            .char_indices()
+            .find(|(_, c)| matches!(c, ':' | '='))
+            .map(|(idx, _)| idx);
+        match split {
+            Some(idx) if SENSITIVE_KEYS.iter().any(|key| lower[..idx].contains(key)) => {
+                out.push_str(&line[..=idx]);
+                out.push(' ');
+                out.push_str(REDACTED);
+            }
+            _ => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    for (value, placeholder) in replacements {
+        if !value.is_empty() {
+            out = replace_word(&out, value, placeholder);
+        }
+    }
+    redact_uuids(&out)
+}
+
+/// Keeps the most recent NVIDIA Xid error reports from kernel log output.
+pub fn xid_lines(log: &str) -> String {
+    let lines: Vec<&str> = log.lines().filter(|line| line.contains("Xid")).collect();
+    let start = lines.len().saturating_sub(MAX_XID_LINES);
+    lines[start..].join("\n")
+}
+
+/// A gzip-compressed tarball of diagnostics, written entry by entry. Every entry goes through
+/// [`redact`]; failures to collect something are recorded in the bundle instead of aborting.
+pub struct Bundle {
+    tar: tar::Builder<GzEncoder<File>>,
+    prefix: String,
+    replacements: Vec<(String, &'static str)>,
+    mtime: u64,
+}
+
+impl Bundle {
+    pub fn create(
+        path: &Path,
+        prefix: &str,
+        replacements: Vec<(String, &'static str)>,
+    ) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .map_err(|err| anyhow::anyhow!("failed to create {}: {err}", path.display()))?;
+        Ok(Self {
+            tar: tar::Builder::new(GzEncoder::new(file, Compression::default())),
+            prefix: prefix.to_string(),
+            replacements,
+            mtime: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+        })
+    }
+
+    pub fn add(&mut self, name: &str, contents: &str) -> anyhow::Result<()> {
+        let contents = redact(contents, &self.replacements);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.mtime);
+        header.set_cksum();
+        self.tar.append_data(
+            &mut header,
+            format!("{}/{name}", self.prefix),
+            contents.as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    pub fn add_result(&mut self, name: &str, result: anyhow::Result<String>) -> anyhow::Result<()> {
+        match result {
+            Ok(contents) => self.add(name, &contents),
+            Err(err) => self.add(&format!("{name}.error"), &format!("{err:#}\n")),
+        }
+    }
+
+    pub fn add_file(&mut self, name: &str, path: &Path) -> anyhow::Result<()> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("failed to read {}: {err}", path.display()));
+        self.add_result(name, contents)
+    }
+
+    /// Adds the output of `program`, stderr included.
+    pub fn add_command(&mut self, name: &str, program: &str, args: &[&str]) -> anyhow::Result<()> {
+        let output = run(program, args);
+        self.add_result(name, output)
+    }
+
+    pub fn finish(self) -> anyhow::Result<()> {
+        self.tar.into_inner()?.finish()?;
+        Ok(())
+    }
+}
+
+/// Runs `program` and returns its stdout followed by any stderr.
+pub fn run(program: &str, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|err| anyhow::anyhow!("failed to run {program}: {err}"))?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.trim().is_empty() {
+        text.push_str("\n--- stderr ---\n");
+        text.push_str(&stderr);
+    }
+    if !output.status.success() {
+        text.push_str(&format!("\n{program} exited with {}\n", output.status));
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_sensitive_values() {
+        let replacements = [
+            ("gpu-node-1".to_string(), "<node-name>"),
+            ("gpu1".to_string(), "<hostname>"),
+        ];
+        let cases = [
+            ("token: abc123", "token: <redacted>"),
+            ("Serial Number = 1234", "Serial Number = <redacted>"),
+            ("model: A100", "model: A100"),
+            ("node gpu-node-1 ready", "node <node-name> ready"),
+            (
+                "gpu-node-10 and gpu-node-1.",
+                "gpu-node-10 and <node-name>.",
+            ),
+            ("host gpu1.example.com", "host <hostname>.example.com"),
+            ("gpu12 mygpu1 gpu1_x", "gpu12 mygpu1 gpu1_x"),
+            (
+                "GPU-6b6b3c0e-0f3a-4d6c-9a5e-2f0a1b2c3d4e MIG-11111111-2222-3333-4444-555555555555",
+                "GPU-<uuid-1> MIG-<uuid-2>",
+            ),
+            (
+                "pod 0b5c7f2e-9d1a-4c3b-8e6f-123456789abc c: 0B5C7F2E-9D1A-4C3B-8E6F-123456789ABC",
+                "pod <uuid-1> c: <uuid-1>",
+            ),
+            (
+                "x6b6b3c0e-0f3a-4d6c-9a5e-2f0a1b2c3d4e",
+                "x6b6b3c0e-0f3a-4d6c-9a5e-2f0a1b2c3d4e",
+            ),
+        ];
+        for (text, want) in cases {
+            assert_eq!(redact(text, &replacements), format!("{want}\n"), "{text}");
+        }
+    }
+
+    #[test]
+    fn keeps_recent_xid_lines() {
+        let log = "[1.0] usb 1-1: new device\n[2.0] NVRM: Xid (PCI:0000:3b:00): 79, GPU has fallen off the bus\n[3.0] eth0: link up\n";
+        assert_eq!(
+            xid_lines(log),
+            "[2.0] NVRM: Xid (PCI:0000:3b:00): 79, GPU has fallen off the bus"
+        );
+
+        let log: String = (0..MAX_XID_LINES + 5)
+            .map(|n| format!("NVRM: Xid {n}\n"))
+            .collect();
+        let kept = xid_lines(&log);
+        assert_eq!(kept.lines().count(), MAX_XID_LINES);
+        assert!(kept.starts_with("NVRM: Xid 5\n"));
+    }
+}
@@ -0,0 +1,203 @@
+use crate::k8s;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+pub const KUBELET_CHECKPOINT_FILE: &str = "kubelet_internal_checkpoint";
+
+#[derive(Deserialize, Debug)]
+struct Checkpoint {
+    #[serde(rename = "Data")]
+    data: CheckpointData,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct CheckpointData {
+    #[serde(rename = "PodDeviceEntries", deserialize_with = "null_as_default")]
+    pod_device_entries: Vec<PodDevicesEntry>,
+    #[serde(rename = "RegisteredDevices", deserialize_with = "null_as_default")]
+    registered_devices: BTreeMap<String, Vec<String>>,
+}
+
+// kubelet is written in Go and stores empty collections as null.
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[derive(Deserialize, Debug)]
+struct PodDevicesEntry {
+    #[serde(rename = "PodUID")]
+    pod_uid: String,
+    #[serde(rename = "ContainerName")]
+    container_name: String,
+    #[serde(rename = "ResourceName")]
+    resource_name: String,
+    #[serde(rename = "DeviceIDs")]
+    device_ids: DeviceIds,
+}
+
+// Kubelets since 1.20 group device IDs by NUMA node; older ones store a flat list.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum DeviceIds {
+    PerNuma(BTreeMap<String, Vec<String>>),
+    Flat(Vec<String>),
+}
+
+impl DeviceIds {
+    fn ids(&self) -> Vec<&str> {
+        match self {
+            DeviceIds::PerNuma(by_node) => by_node.values().flatten().map(String::as_str).collect(),
+            DeviceIds::Flat(ids) => ids.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+fn read_checkpoint(path: &Path) -> anyhow::Result<Checkpoint> {
+    let raw = fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("failed to read {}: {err}", path.display()))?;
+    serde_json::from_str(&raw)
+        .map_err(|err| anyhow::anyhow!("failed to parse {}: {err}", path.display()))
+}
+
+/// The parts of the checkpoint concerning `resource_names`, without the allocation responses
+/// kubelet stores alongside (they carry container environment).
+pub fn excerpt(path: &Path, resource_names: &[String]) -> anyhow::Result<String> {
+    let checkpoint = read_checkpoint(path)?;
+    let mut out = String::new();
+    for entry in &checkpoint.data.pod_device_entries {
+        if !resource_names.contains(&entry.resource_name) {
+            continue;
+        }
+        out.push_str(&format!(
+            "pod {} container {} {}: {}\n",
+            entry.pod_uid,
+            entry.container_name,
+            entry.resource_name,
+            entry.device_ids.ids().join(",")
+        ));
+    }
+    for resource_name in resource_names {
+        if let Some(ids) = checkpoint.data.registered_devices.get(resource_name) {
+            out.push_str(&format!("registered {resource_name}: {}\n", ids.join(",")));
+        }
+    }
+    Ok(out)
+}
+
+/// Prints which pods own which of our device IDs according to kubelet's device manager
+/// checkpoint, flagging IDs that the plugin itself does not know about.
+pub fn print_checkpoint(
+    path: &Path,
+    resource_name: &str,
+    devices: &BTreeMap<String, k8s::Device>,
+) -> anyhow::Result<()> {
+    let checkpoint = read_checkpoint(path)?;
+
+    println!("checkpoint: {}", path.display());
+    println!("resource:   {resource_name}");
+    println!();
+    println!(
+        "{:<32} {:<38} {:<24} STATE",
+        "DEVICE", "POD UID", "CONTAINER"
+    );
+
+    let mut owned = BTreeSet::new();
+    for entry in &checkpoint.data.pod_device_entries {
+        if entry.resource_name != resource_name {
+            continue;
+        }
+        for id in entry.device_ids.ids() {
+            let state = match devices.get(id) {
+                Some(dev) => dev.health.clone(),
+                None => "unknown to plugin".to_string(),
+            };
+            println!(
+                "{:<32} {:<38} {:<24} {state}",
+                id, entry.pod_uid, entry.container_name
+            );
+            owned.insert(id.to_string());
+        }
+    }
+    for (id, dev) in devices {
+        if !owned.contains(id) {
+            println!("{:<32} {:<38} {:<24} {} (free)", id, "-", "-", dev.health);
+        }
+    }
+
+    let registered: BTreeSet<&str> = checkpoint
+        .data
+        .registered_devices
+        .get(resource_name)
+        .map(|ids| ids.iter().map(String::as_str).collect())
+        .unwrap_or_default();
+    let discovered: BTreeSet<&str> = devices.keys().map(String::as_str).collect();
+
+    let stale: Vec<&str> = registered.difference(&discovered).copied().collect();
+    let unregistered: Vec<&str> = discovered.difference(&registered).copied().collect();
+    if !stale.is_empty() {
+        println!();
+        println!(
+            "registered with kubelet but not discovered by plugin: {}",
+            stale.join(", ")
+        );
+    }
+    if !unregistered.is_empty() {
+        println!();
+        println!(
+            "discovered by plugin but not registered with kubelet: {}",
+            unregistered.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Checkpoint {
+        serde_json::from_str(raw).unwrap()
+    }
+
+    #[test]
+    fn reads_device_ids_in_both_layouts() {
+        let cases = [
+            (
+                r#"{"Data":{"PodDeviceEntries":[{"PodUID":"a","ContainerName":"c","ResourceName":"nvidia.com/gpu","DeviceIDs":{"0":["nvidia.com/gpu=0"],"1":["nvidia.com/gpu=1"]},"AllocResp":"Cg=="}],"RegisteredDevices":{"nvidia.com/gpu":["nvidia.com/gpu=0","nvidia.com/gpu=1"]}},"Checksum":1}"#,
+                vec!["nvidia.com/gpu=0", "nvidia.com/gpu=1"],
+            ),
+            (
+                r#"{"Data":{"PodDeviceEntries":[{"PodUID":"a","ContainerName":"c","ResourceName":"nvidia.com/gpu","DeviceIDs":["nvidia.com/gpu=1"],"AllocResp":"Cg=="}]},"Checksum":1}"#,
+                vec!["nvidia.com/gpu=1"],
+            ),
+        ];
+        for (raw, want) in cases {
+            let checkpoint = parse(raw);
+            let entry = &checkpoint.data.pod_device_entries[0];
+            assert_eq!(entry.resource_name, "nvidia.com/gpu");
+            assert_eq!(entry.device_ids.ids(), want, "{raw}");
+        }
+    }
+
+    #[test]
+    fn tolerates_empty_data() {
+        for raw in [
+            r#"{"Data":{"PodDeviceEntries":null,"RegisteredDevices":null},"Checksum":0}"#,
+            r#"{"Data":{},"Checksum":0}"#,
+        ] {
+            let checkpoint = parse(raw);
+            assert!(checkpoint.data.pod_device_entries.is_empty());
+            assert!(checkpoint.data.registered_devices.is_empty());
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use crate::{
+    gpu::GpuInfo, peercred::PeerPolicy, persistence, podresources, rpc::RpcLimits, socket,
+    unix_channel, SharedDevices,
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{process::Command, select, sync::watch, task::JoinHandle, time::interval};
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::{async_trait, Request, Response, Status};
+
+pub mod api {
+    tonic::include_proto!("devicemap.v1");
+}
+
+pub const DEVICE_MAP_SOCKET_NAME: &str = "devices.sock";
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Parses `nvidia-smi -L` into the MIG devices configured on each GPU, keyed by GPU UUID.
+fn parse_mig_list(output: &str) -> BTreeMap<String, Vec<api::MigDevice>> {
+    let mut out: BTreeMap<String, Vec<api::MigDevice>> = BTreeMap::new();
+
+    // GPU 0: NVIDIA A100-SXM4-40GB (UUID: GPU-...)
+    //   MIG 1g.5gb      Device  0: (UUID: MIG-...)
+    let mut gpu_uuid = None;
+    for line in output.lines() {
+        let uuid = line
+            .split_once("(UUID: ")
+            .and_then(|(_, rest)| rest.strip_suffix(')'))
+            .map(str::to_string);
+        let line = line.trim_start();
+        if line.starts_with("GPU ") {
+            gpu_uuid = uuid;
+        } else if let Some(rest) = line.strip_prefix("MIG ")
+            && let (Some(gpu_uuid), Some(uuid)) = (&gpu_uuid, uuid)
+        {
+            let mut fields = rest.split_whitespace();
+            let profile = fields.next().unwrap_or_default().to_string();
+            let index = fields
+                .skip_while(|field| *field != "Device")
+                .nth(1)
+                .and_then(|index| index.trim_end_matches(':').parse().ok())
+                .unwrap_or_default();
+            out.entry(gpu_uuid.clone())
+                .or_default()
+                .push(api::MigDevice {
+                    uuid,
+                    profile,
+                    index,
+                });
+        }
+    }
+    out
+}
+
+/// MIG devices configured on each GPU. Empty if nvidia-smi is unavailable.
+async fn mig_devices() -> BTreeMap<String, Vec<api::MigDevice>> {
+    match Command::new("nvidia-smi").arg("-L").output().await {
+        Ok(output) => parse_mig_list(&String::from_utf8_lossy(&output.stdout)),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+/// What the device map reports beyond the advertised devices. Collected in the background, so
+/// that a burst of calls does not each run nvidia-smi and query kubelet.
+#[derive(Debug, Default)]
+pub struct Details {
+    // Keyed by GPU UUID.
+    migs: BTreeMap<String, Vec<api::MigDevice>>,
+    // Keyed by PCI bus ID.
+    persistence: BTreeMap<String, bool>,
+    // Keyed by resource name and device ID.
+    pods: BTreeMap<(String, String), Vec<api::Pod>>,
+}
+
+impl Details {
+    async fn collect(pod_resources_socket: &Path) -> Self {
+        // Allocation state is best effort: the identifiers are still useful without kubelet.
+        let mut pods: BTreeMap<(String, String), Vec<api::Pod>> = BTreeMap::new();
+        match podresources::list_assignments(pod_resources_socket).await {
+            Ok(assignments) => {
+                for a in assignments {
+                    pods.entry((a.resource_name, a.device_id))
+                        .or_default()
+                        .push(api::Pod {
+                            namespace: a.pod_namespace,
+                            name: a.pod_name,
+                        });
+                }
+            }
+            Err(err) => eprintln!("device map: failed to list pod resources: {err}"),
+        }
+        Self {
+            migs: mig_devices().await,
+            persistence: tokio::task::spawn_blocking(persistence::query)
+                .await
+                .unwrap_or_default(),
+            pods,
+        }
+    }
+}
+
+pub type SharedDetails = Arc<watch::Sender<Arc<Details>>>;
+
+pub fn details() -> SharedDetails {
+    Arc::new(watch::Sender::new(Arc::new(Details::default())))
+}
+
+/// Keeps `details` up to date for the device map server.
+pub fn spawn_refresher(
+    details: SharedDetails,
+    pod_resources_socket: PathBuf,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = interval(REFRESH_INTERVAL);
+        loop {
+            select! {
+                _ = ticks.tick() => {},
+                changed = shutdown.changed() => {
+                    if changed.is_err() || *shutdown.borrow() {
+                        break;
+                    }
+                    continue;
+                }
+            }
+            details.send_replace(Arc::new(Details::collect(&pod_resources_socket).await));
+        }
+    })
+}
+
+/// Serves [`api::device_map_server::DeviceMap`]: what each advertised device ID refers to and
+/// who is using it.
+pub struct DeviceMapService {
+    resources: Vec<(String, SharedDevices)>,
+    // Driver view of the GPU behind each device ID; other resources have no entry.
+    gpus: BTreeMap<String, GpuInfo>,
+    details: SharedDetails,
+}
+
+impl DeviceMapService {
+    pub fn new(
+        resources: Vec<(String, SharedDevices)>,
+        gpus: BTreeMap<String, GpuInfo>,
+        details: SharedDetails,
+    ) -> Self {
+        Self {
+            resources,
+            gpus,
+            details,
+        }
+    }
+}
+
+#[async_trait]
+impl api::device_map_server::DeviceMap for DeviceMapService {
+    async fn list_devices(
+        &self,
+        request: Request<api::ListDevicesRequest>,
+    ) -> Result<Response<api::ListDevicesResponse>, Status> {
+        let filter = request.into_inner().resource_name;
+        let details = self.details.borrow().clone();
+
+        let mut devices = Vec::new();
+        for (resource_name, store) in &self.resources {
+            if !filter.is_empty() && *resource_name != filter {
+                continue;
+            }
+            let snapshot = store.snapshot();
+            for (id, dev) in snapshot.devices() {
+                let gpu = self.gpus.get(id);
+                devices.push(api::Device {
+                    resource_name: resource_name.clone(),
+                    device_id: id.clone(),
+                    uuid: gpu.map(|gpu| gpu.uuid.clone()).unwrap_or_default(),
+                    pci_bus_id: gpu.map(|gpu| gpu.pci_bus_id.clone()).unwrap_or_default(),
+                    model: gpu.map(|gpu| gpu.model.clone()).unwrap_or_default(),
+                    numa_node: crate::numa_node(dev).unwrap_or(-1),
+                    health: dev.health.clone(),
+                    mig_devices: gpu
+                        .and_then(|gpu| details.migs.get(&gpu.uuid).cloned())
+                        .unwrap_or_default(),
+                    pods: details
+                        .pods
+                        .get(&(resource_name.clone(), id.clone()))
+                        .cloned()
+                        .unwrap_or_default(),
+                    persistence_mode: gpu
+                        .and_then(|gpu| details.persistence.get(&gpu.pci_bus_id))
+                        .copied(),
+                });
+            }
+        }
+
+        Ok(Response::new(api::ListDevicesResponse { devices }))
+    }
+}
+
+pub fn start_device_map_server(
+    socket_path: PathBuf,
+    service: DeviceMapService,
+    peers: PeerPolicy,
+    limits: RpcLimits,
+    force: bool,
+) -> anyhow::Result<JoinHandle<()>> {
+    if let Some(dir) = socket_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let uds = socket::bind(&socket_path, force)?;
+    let incoming = UnixListenerStream::new(uds);
+    let service = api::device_map_server::DeviceMapServer::with_interceptor(service, peers);
+
+    Ok(tokio::spawn(async move {
+        if let Err(err) = limits
+            .server()
+            .add_service(service)
+            .serve_with_incoming(incoming)
+            .await
+        {
+            eprintln!("device map gRPC server crashed: {err}");
+        }
+    }))
+}
+
+/// Prints the device map served on `socket_path`.
+pub async fn print_devices(
+    socket_path: PathBuf,
+    resource_name: Option<&str>,
+) -> anyhow::Result<()> {
+    let channel = unix_channel(socket_path.clone())
+        .await
+        .map_err(|err| anyhow::anyhow!("failed to connect to {}: {err}", socket_path.display()))?;
+    let mut client = api::device_map_client::DeviceMapClient::new(channel);
+    let resp = client
+        .list_devices(api::ListDevicesRequest {
+            resource_name: resource_name.unwrap_or_default().to_string(),
+        })
+        .await?
+        .into_inner();
+
+    println!(
+        "{:<32} {:<42} {:<14} {:<10} {:<11} PODS",
+        "DEVICE", "UUID", "PCI BUS ID", "HEALTH", "PERSISTENCE"
+    );
+    for dev in &resp.devices {
+        let pods: Vec<String> = dev
+            .pods
+            .iter()
+            .map(|pod| format!("{}/{}", pod.namespace, pod.name))
+            .collect();
+        let persistence = match dev.persistence_mode {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => "-",
+        };
+        println!(
+            "{:<32} {:<42} {:<14} {:<10} {:<11} {}",
+            dev.device_id,
+            dev.uuid,
+            dev.pci_bus_id,
+            dev.health,
+            persistence,
+            if pods.is_empty() {
+                "-".to_string()
+            } else {
+                pods.join(",")
+            }
+        );
+        for mig in &dev.mig_devices {
+            println!("  MIG {} {} ({})", mig.index, mig.profile, mig.uuid);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mig_list() {
+        let output = "\
+GPU 0: NVIDIA A100-SXM4-40GB (UUID: GPU-5d9e2bcc-2a4f-4c1b-9c3e-8e0f6a1b2c3d)
+  MIG 3g.20gb     Device  0: (UUID: MIG-0a1b2c3d-4e5f-5a6b-8c7d-9e0f1a2b3c4d)
+  MIG 1g.5gb      Device  1: (UUID: MIG-1b2c3d4e-5f6a-5b7c-8d9e-0f1a2b3c4d5e)
+GPU 1: NVIDIA A100-SXM4-40GB (UUID: GPU-6e0f3cdd-3b5a-4d2c-8d4f-9f1a7b2c3d4e)
+";
+        let migs = parse_mig_list(output);
+        assert_eq!(migs.len(), 1);
+        let devices = &migs["GPU-5d9e2bcc-2a4f-4c1b-9c3e-8e0f6a1b2c3d"];
+        let got: Vec<(&str, &str, u32)> = devices
+            .iter()
+            .map(|mig| (mig.uuid.as_str(), mig.profile.as_str(), mig.index))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("MIG-0a1b2c3d-4e5f-5a6b-8c7d-9e0f1a2b3c4d", "3g.20gb", 0),
+                ("MIG-1b2c3d4e-5f6a-5b7c-8d9e-0f1a2b3c4d5e", "1g.5gb", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_unparseable_mig_list() {
+        for output in [
+            "",
+            "No devices found.\n",
+            "  MIG 1g.5gb Device 0: (UUID: MIG-x)\n",
+        ] {
+            assert!(parse_mig_list(output).is_empty(), "{output:?}");
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::gpu;
+use std::{
+    ffi::CString,
+    fs,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const NVIDIACTL_MINOR: u32 = 255;
+
+// A device node we expect once the driver is loaded, and how to recreate it.
+struct Node {
+    path: PathBuf,
+    // Driver names under which the major number may be registered in /proc/devices.
+    drivers: &'static [&'static str],
+    minor: u32,
+    modprobe_args: Vec<String>,
+}
+
+fn expected_nodes() -> Vec<Node> {
+    let frontend: &[&str] = &["nvidia-frontend", "nvidia"];
+    let mut nodes: Vec<Node> = gpu::discover_gpus()
+        .into_iter()
+        .map(|gpu| Node {
+            path: PathBuf::from(format!("/dev/nvidia{}", gpu.minor)),
+            drivers: frontend,
+            minor: gpu.minor,
+            modprobe_args: vec!["-c".to_string(), gpu.minor.to_string()],
+        })
+        .collect();
+    nodes.push(Node {
+        path: PathBuf::from("/dev/nvidiactl"),
+        drivers: frontend,
+        minor: NVIDIACTL_MINOR,
+        modprobe_args: vec!["-c".to_string(), NVIDIACTL_MINOR.to_string()],
+    });
+    for (name, minor) in [("nvidia-uvm", 0), ("nvidia-uvm-tools", 1)] {
+        nodes.push(Node {
+            path: PathBuf::from(format!("/dev/{name}")),
+            drivers: &["nvidia-uvm"],
+            minor,
+            modprobe_args: vec!["-u".to_string(), "-c".to_string(), "0".to_string()],
+        });
+    }
+    nodes
+}
+
+// Major number of a character device driver as listed in /proc/devices.
+fn char_major(drivers: &[&str]) -> Option<u32> {
+    let devices = fs::read_to_string("/proc/devices").ok()?;
+    devices
+        .lines()
+        .skip_while(|line| !line.starts_with("Character devices:"))
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| {
+            let (major, name) = line.trim().split_once(' ')?;
+            drivers.contains(&name).then(|| major.parse().ok())?
+        })
+}
+
+fn nvidia_modprobe(args: &[String]) -> bool {
+    Command::new("nvidia-modprobe")
+        .args(args)
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn mknod(path: &Path, major: u32, minor: u32) -> anyhow::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let dev = libc::makedev(major, minor);
+    if unsafe { libc::mknod(c_path.as_ptr(), libc::S_IFCHR | 0o666, dev) } != 0 {
+        anyhow::bail!(
+            "mknod {} failed: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        );
+    }
+    // mknod is subject to the umask.
+    fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+    Ok(())
+}
+
+/// Creates `/dev/nvidia*` nodes the loaded driver should have but that are missing, as happens on
+/// minimal OSes without NVIDIA's udev rules. Tries nvidia-modprobe first and falls back to mknod
+/// with the major numbers from /proc/devices. Needs CAP_MKNOD and a writable /dev.
+pub fn create_missing() {
+    if !gpu::driver_loaded() {
+        return;
+    }
+
+    for node in expected_nodes() {
+        if node.path.exists() {
+            continue;
+        }
+        if nvidia_modprobe(&node.modprobe_args) && node.path.exists() {
+            println!("created {} with nvidia-modprobe", node.path.display());
+            continue;
+        }
+        let Some(major) = char_major(node.drivers) else {
+            eprintln!(
+                "warning: cannot create {}: {} is not registered in /proc/devices",
+                node.path.display(),
+                node.drivers.join("/")
+            );
+            continue;
+        };
+        match mknod(&node.path, major, node.minor) {
+            Ok(()) => println!("created {} ({major}:{})", node.path.display(), node.minor),
+            Err(err) => eprintln!("warning: {err}"),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{gpu::normalize_bus_id, store::HealthReason, SharedDevices};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+use tokio::{process::Command, select, sync::watch, task::JoinHandle, time::interval};
+
+/// Fabric state of one GPU as reported by NVML (through nvidia-smi).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FabricInfo {
+    pub state: String,
+    pub status: String,
+}
+
+impl FabricInfo {
+    /// Why the GPU cannot be used, or `None` if it can. GPUs outside an NVSwitch fabric report
+    /// their state as not supported and are always usable.
+    pub fn problem(&self) -> Option<String> {
+        match self.state.as_str() {
+            "Completed" if matches!(self.status.as_str(), "Success" | "N/A") => None,
+            "Completed" => Some(format!("NVLink fabric is degraded ({})", self.status)),
+            "N/A" | "Not Supported" | "[N/A]" | "[Not Supported]" => None,
+            "Not Started" => {
+                Some("NVLink fabric not initialized; is nvidia-fabricmanager running?".to_string())
+            }
+            "In Progress" => Some("NVLink fabric initialization in progress".to_string()),
+            state => Some(format!("unexpected NVLink fabric state {state}")),
+        }
+    }
+}
+
+/// Fabric state of each GPU, keyed by PCI bus ID.
+pub async fn query() -> anyhow::Result<BTreeMap<String, FabricInfo>> {
+    let output = Command::new("nvidia-smi")
+        .args([
+            "--query-gpu=pci.bus_id,fabric.state,fabric.status",
+            "--format=csv,noheader",
+        ])
+        .output()
+        .await
+        .map_err(|err| anyhow::anyhow!("failed to run nvidia-smi: {err}"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "nvidia-smi could not report fabric state: {}",
+            String::from_utf8_lossy(&output.stdout).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(',').map(str::trim);
+            let bus_id = normalize_bus_id(fields.next()?);
+            let info = FabricInfo {
+                state: fields.next()?.to_string(),
+                status: fields.next()?.to_string(),
+            };
+            Some((bus_id, info))
+        })
+        .collect())
+}
+
+/// Periodically checks the NVLink fabric and reports GPUs that are not part of a working fabric
+/// as unhealthy in every store in `stores`. `bus_ids` maps device IDs to PCI bus IDs.
+pub fn spawn_fabric_monitor(
+    stores: Vec<SharedDevices>,
+    bus_ids: BTreeMap<String, String>,
+    period: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = interval(period);
+        // Devices with a fabric problem and why, to log only changes. Seeded from the stores so
+        // that devices marked before the task was restarted are reported when they recover.
+        let mut masked: BTreeMap<String, String> = stores
+            .iter()
+            .flat_map(|store| {
+                let snapshot = store.snapshot();
+                snapshot
+                    .devices()
+                    .keys()
+                    .filter(|id| snapshot.has_reason(id, HealthReason::Fabric))
+                    .map(|id| (id.clone(), String::new()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let mut query_failed = false;
+        loop {
+            select! {
+                _ = ticks.tick() => {},
+                changed = shutdown.changed() => {
+                    if changed.is_err() || *shutdown.borrow() {
+                        break;
+                    }
+                    continue;
+                }
+            }
+
+            let fabric = match query().await {
+                Ok(fabric) => {
+                    query_failed = false;
+                    fabric
+                }
+                Err(err) => {
+                    // Leave device health as it is rather than guessing.
+                    if !query_failed {
+                        eprintln!("warning: failed to check NVLink fabric state: {err:#}");
+                    }
+                    query_failed = true;
+                    continue;
+                }
+            };
+
+            let problems: BTreeMap<&str, String> = bus_ids
+                .iter()
+                .filter_map(|(id, bus_id)| Some((id.as_str(), fabric.get(bus_id)?.problem()?)))
+                .collect();
+            for (id, reason) in &problems {
+                if masked.get(*id) != Some(reason) {
+                    eprintln!("marking {id} unhealthy: {reason}");
+                }
+            }
+            let recovered: BTreeSet<String> = masked
+                .keys()
+                .filter(|id| !problems.contains_key(id.as_str()))
+                .cloned()
+                .collect();
+            for id in &recovered {
+                println!("{id} is healthy again: NVLink fabric is up");
+            }
+
+            for store in &stores {
+                store.set_unhealthy_where(HealthReason::Fabric, |id| problems.contains_key(id));
+            }
+            for id in recovered {
+                masked.remove(&id);
+            }
+            for (id, reason) in problems {
+                masked.insert(id.to_string(), reason);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_unusable_fabric_states() {
+        let cases = [
+            ("Completed", "Success", false),
+            ("Completed", "N/A", false),
+            ("Completed", "Failure", true),
+            ("N/A", "N/A", false),
+            ("[Not Supported]", "[Not Supported]", false),
+            ("Not Started", "N/A", true),
+            ("In Progress", "N/A", true),
+            ("Bogus", "N/A", true),
+        ];
+        for (state, status, unusable) in cases {
+            let info = FabricInfo {
+                state: state.to_string(),
+                status: status.to_string(),
+            };
+            assert_eq!(info.problem().is_some(), unusable, "{state}/{status}");
+        }
+    }
+}
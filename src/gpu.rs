@@ -0,0 +1,145 @@
+use std::{fs, path::Path};
+
+const NVIDIA_PROC_GPUS_DIR: &str = "/proc/driver/nvidia/gpus";
+pub const PCI_DEVICES_DIR: &str = "/sys/bus/pci/devices";
+
+/// Attributes of a GPU as reported by the driver under /proc/driver/nvidia/gpus.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GpuInfo {
+    pub minor: u32,
+    pub pci_bus_id: String,
+    pub uuid: String,
+    pub model: String,
+    pub numa_node: Option<i64>,
+}
+
+/// Converts a bus ID as printed by nvidia-smi, with an 8-digit PCI domain ("00000000:3B:00.0"),
+/// to the form procfs and sysfs use ("0000:3b:00.0").
+pub fn normalize_bus_id(raw: &str) -> String {
+    let raw = raw.trim().to_lowercase();
+    match raw.split_once(':') {
+        Some((domain, rest)) if domain.len() > 4 => {
+            format!("{}:{rest}", &domain[domain.len() - 4..])
+        }
+        _ => raw,
+    }
+}
+
+/// Whether the NVIDIA kernel driver is loaded and has enumerated its GPUs.
+pub fn driver_loaded() -> bool {
+    Path::new(NVIDIA_PROC_GPUS_DIR).is_dir()
+}
+
+/// Returns the GPUs known to the loaded driver, ordered by device minor.
+pub fn discover_gpus() -> Vec<GpuInfo> {
+    let Ok(entries) = fs::read_dir(NVIDIA_PROC_GPUS_DIR) else {
+        return Vec::new();
+    };
+
+    let mut gpus: Vec<GpuInfo> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let contents = fs::read_to_string(entry.path().join("information")).ok()?;
+            parse_information(&contents)
+        })
+        .collect();
+    gpus.sort_by_key(|gpu| gpu.minor);
+    gpus
+}
+
+fn parse_information(contents: &str) -> Option<GpuInfo> {
+    let mut info = GpuInfo::default();
+    let mut minor = None;
+
+    for line in contents.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "Model" => info.model = value.to_string(),
+            "Bus Location" => info.pci_bus_id = value.to_lowercase(),
+            "GPU UUID" => info.uuid = value.to_string(),
+            "Device Minor" => minor = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    info.minor = minor?;
+    info.numa_node = pci_numa_node(&info.pci_bus_id);
+    Some(info)
+}
+
+pub fn pci_numa_node(pci_bus_id: &str) -> Option<i64> {
+    let raw = fs::read_to_string(
+        Path::new(PCI_DEVICES_DIR)
+            .join(pci_bus_id)
+            .join("numa_node"),
+    )
+    .ok()?;
+    raw.trim().parse().ok().filter(|node| *node >= 0)
+}
+
+/// Identifies the model of a GPU the driver does not manage by its PCI device ID, e.g.
+/// `10de:2330`.
+pub fn pci_model(pci_bus_id: &str) -> Option<String> {
+    let dir = Path::new(PCI_DEVICES_DIR).join(pci_bus_id);
+    let vendor = fs::read_to_string(dir.join("vendor")).ok()?;
+    let device = fs::read_to_string(dir.join("device")).ok()?;
+    Some(format!(
+        "{}:{}",
+        vendor.trim().trim_start_matches("0x"),
+        device.trim().trim_start_matches("0x")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_driver_information() {
+        let contents = "\
+Model: \t\t NVIDIA A100-SXM4-40GB
+IRQ:   \t\t 152
+GPU UUID: \t GPU-6b6b3c0e-0f3a-4d6c-9a5e-2f0a1b2c3d4e
+Video BIOS: \t 92.00.19.00.01
+Bus Type: \t PCIe
+DMA Size: \t 47 bits
+DMA Mask: \t 0x7fffffffffff
+Bus Location: \t 0000:3B:00.0
+Device Minor: \t 2
+GPU Excluded:\t No
+";
+        let info = parse_information(contents).unwrap();
+        assert_eq!(info.minor, 2);
+        assert_eq!(info.model, "NVIDIA A100-SXM4-40GB");
+        assert_eq!(info.uuid, "GPU-6b6b3c0e-0f3a-4d6c-9a5e-2f0a1b2c3d4e");
+        assert_eq!(info.pci_bus_id, "0000:3b:00.0");
+    }
+
+    #[test]
+    fn normalizes_bus_ids() {
+        let cases = [
+            ("00000000:3B:00.0", "0000:3b:00.0"),
+            ("00000001:c1:00.0", "0001:c1:00.0"),
+            ("0000:3b:00.0", "0000:3b:00.0"),
+            (" 00000000:3B:00.0\n", "0000:3b:00.0"),
+        ];
+        for (raw, want) in cases {
+            assert_eq!(normalize_bus_id(raw), want, "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn requires_device_minor() {
+        let cases = [
+            "Model: NVIDIA A100\nBus Location: 0000:3b:00.0\n",
+            "Model: NVIDIA A100\nDevice Minor: n/a\n",
+            "",
+        ];
+        for contents in cases {
+            assert_eq!(parse_information(contents), None, "{contents:?}");
+        }
+    }
+}
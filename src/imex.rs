@@ -0,0 +1,134 @@
+use crate::{k8s, store::HealthReason, SharedDevices};
+use clap::ValueEnum;
+use std::{collections::BTreeMap, fs, io, time::Duration};
+use tokio::{process::Command, select, sync::watch, task::JoinHandle, time::interval};
+
+pub const DEFAULT_IMEX_RESOURCE_NAME: &str = "nvidia.com/imex-channel";
+pub const DEFAULT_IMEX_SOCKET_NAME: &str = "nvidia-cdi-imex-channel.sock";
+pub const IMEX_CHANNEL_DIR: &str = "/dev/nvidia-caps-imex-channels";
+// nvidia-imex-ctl reads the daemon's command port from here.
+pub const IMEX_CONFIG_DIR: &str = "/etc/nvidia-imex";
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How IMEX channels (needed for multi-node NVLink on GB200/NVL systems) are handed to workloads.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// don't handle IMEX channels
+    Off,
+    /// advertise them as a separate resource
+    Resource,
+    /// inject every channel alongside each GPU allocation
+    Inject,
+}
+
+/// Returns the numbers of the IMEX channel device nodes, e.g. 0 for `channel0`.
+pub fn discover_channels() -> Vec<u32> {
+    let Ok(entries) = fs::read_dir(IMEX_CHANNEL_DIR) else {
+        return Vec::new();
+    };
+    let mut channels: Vec<u32> = entries
+        .flatten()
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("channel")?
+                .parse()
+                .ok()
+        })
+        .collect();
+    channels.sort_unstable();
+    channels
+}
+
+/// CDI names of `channels`; the resource name must match the IMEX channel CDI kind.
+pub fn cdi_names(resource_name: &str, channels: &[u32]) -> Vec<String> {
+    channels
+        .iter()
+        .map(|channel| format!("{resource_name}={channel}"))
+        .collect()
+}
+
+pub fn channel_devices(resource_name: &str, channels: &[u32]) -> BTreeMap<String, k8s::Device> {
+    cdi_names(resource_name, channels)
+        .into_iter()
+        .map(|id| {
+            let dev = k8s::Device {
+                id: id.clone(),
+                health: "Healthy".to_string(),
+                topology: None,
+            };
+            (id, dev)
+        })
+        .collect()
+}
+
+// Asks the local IMEX daemon whether it is ready. `None` if nvidia-imex-ctl is not installed.
+async fn daemon_ready() -> Option<Result<(), String>> {
+    let output = match Command::new("nvidia-imex-ctl").arg("-q").output().await {
+        Ok(output) => output,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+        Err(err) => return Some(Err(format!("failed to run nvidia-imex-ctl: {err}"))),
+    };
+    let status = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() && status == "READY" {
+        return Some(Ok(()));
+    }
+    Some(Err(if status.is_empty() {
+        String::from_utf8_lossy(&output.stderr).trim().to_string()
+    } else {
+        status
+    }))
+}
+
+/// Periodically checks the IMEX daemon. While it is not ready the devices in `stores` are
+/// reported unhealthy: the channels when they are a resource, the GPUs they are injected into
+/// otherwise.
+pub fn spawn_health_checker(
+    stores: Vec<SharedDevices>,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = interval(HEALTH_CHECK_INTERVAL);
+        // Picks up where a previous run of this task left off, so a daemon that recovered while
+        // it was being restarted is noticed.
+        let mut healthy = !stores.iter().any(|store| {
+            let snapshot = store.snapshot();
+            snapshot
+                .devices()
+                .keys()
+                .any(|id| snapshot.has_reason(id, HealthReason::Imex))
+        });
+        loop {
+            select! {
+                _ = ticks.tick() => {},
+                changed = shutdown.changed() => {
+                    if changed.is_err() || *shutdown.borrow() {
+                        break;
+                    }
+                    continue;
+                }
+            }
+
+            let ready = match daemon_ready().await {
+                None => {
+                    eprintln!("warning: nvidia-imex-ctl not found, not checking the IMEX daemon");
+                    // Stay up until shutdown so the supervisor does not treat this as a crash.
+                    let _ = shutdown.wait_for(|stop| *stop).await;
+                    break;
+                }
+                Some(ready) => ready,
+            };
+            match &ready {
+                Ok(()) if !healthy => println!("IMEX daemon is ready again"),
+                Err(reason) if healthy => eprintln!("IMEX daemon is not ready: {reason}"),
+                _ => {}
+            }
+            healthy = ready.is_ok();
+
+            for store in &stores {
+                store.set_unhealthy_where(HealthReason::Imex, |_| !healthy);
+            }
+        }
+    })
+}
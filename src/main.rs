@@ -1,27 +1,62 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use futures::{future, FutureExt};
 use glob::glob;
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet, HashMap},
     path::{Path, PathBuf},
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
-    net::{UnixListener, UnixStream},
+    net::UnixStream,
     select,
-    sync::{mpsc, watch, Mutex},
+    signal::unix::{signal, SignalKind},
+    sync::{mpsc, watch, Mutex, Notify},
     task::JoinHandle,
     time::sleep,
 };
 use tokio_stream::wrappers::{ReceiverStream, UnixListenerStream};
 use tonic::{
     async_trait,
-    transport::{Endpoint, Server},
+    transport::{Channel, Endpoint},
     Request, Response, Status,
 };
 use tower::service_fn;
 use hyper_util::rt::TokioIo;
 
+mod admin;
+mod bundle;
+mod checkpoint;
+mod devicemap;
+mod devnodes;
+mod fabric;
+mod gpu;
+mod imex;
+mod models;
+mod nfd;
+mod node_annotations;
+mod nrt;
+mod occupancy;
+mod peercred;
+mod persistence;
+mod podresources;
+mod preferred;
+mod preflight;
+mod privileges;
+mod rdma;
+mod retirement;
+mod rpc;
+mod sandbox;
+mod socket;
+mod sriov;
+mod startup;
+mod store;
+mod supervisor;
+mod systemd;
+mod topology;
+mod versions;
+mod vfio;
+
 pub mod k8s {
     tonic::include_proto!("v1beta1");
 }
@@ -29,6 +64,11 @@ pub mod k8s {
 const DEFAULT_KUBELET_DIR: &str = "/var/lib/kubelet/device-plugins";
 const DEFAULT_SOCKET_NAME: &str = "nvidia-cdi-device-plugin.sock";
 const DEFAULT_RESOURCE_NAME: &str = "nvidia.com/gpu";
+const DEFAULT_GPUDIRECT_RESOURCE_NAME: &str = "nvidia.com/gpudirect";
+const DEFAULT_GPUDIRECT_SOCKET_NAME: &str = "nvidia-cdi-gpudirect.sock";
+const DEFAULT_SRIOV_RESOURCE_NAME: &str = "nvidia.com/gpu-vf";
+const DEFAULT_SRIOV_SOCKET_NAME: &str = "nvidia-cdi-gpu-vf.sock";
+const DEFAULT_STATE_DIR: &str = "/var/lib/nvidia-cdi-device-plugin";
 const DEVICE_PLUGIN_VERSION: &str = "v1beta1";
 const DEVICE_GLOB: &str = "/dev/nvidia[0-9]*";
 
@@ -39,6 +79,14 @@ struct Args {
     #[arg(long, default_value = DEFAULT_RESOURCE_NAME)]
     resource_name: String,
 
+    /// how devices are handed to containers: by CDI name, or as VFIO groups for VM-based runtimes
+    #[arg(long, value_enum, default_value_t = Mode::Cdi)]
+    mode: Mode,
+
+    /// also inject CDI devices named after the device ID for VFIO-backed devices
+    #[arg(long)]
+    vfio_cdi: bool,
+
     /// kubelet device plugin directory
     #[arg(long, default_value = DEFAULT_KUBELET_DIR)]
     kubelet_dir: String,
@@ -46,20 +94,338 @@ struct Args {
     /// unix domain socket name for this plugin
     #[arg(long, default_value = DEFAULT_SOCKET_NAME)]
     socket_name: String,
+
+    /// also advertise the same devices under this resource name (repeatable); a device allocated
+    /// under one name is unavailable under the others
+    #[arg(long = "alias-resource-name", value_name = "NAME")]
+    alias_resource_names: Vec<String>,
+
+    /// what to do when the discovered GPUs are not all the same model
+    #[arg(long, value_enum, default_value_t = models::Policy::Warn)]
+    mixed_models: models::Policy,
+
+    /// name of the node this plugin runs on (usually set via the downward API)
+    #[arg(long, env = "NODE_NAME")]
+    node_name: Option<String>,
+
+    /// publish driver/CUDA/CDI/plugin versions as node annotations (requires node patch RBAC)
+    #[arg(long)]
+    annotate_node: bool,
+
+    /// create missing /dev/nvidia* nodes at startup (needs to start as root with a writable /dev)
+    #[arg(long)]
+    create_device_nodes: bool,
+
+    /// enable persistence mode on all GPUs at startup (needs to start as root)
+    #[arg(long)]
+    persistence_mode: bool,
+
+    /// write an NFD local feature file describing the node's GPUs (optionally to PATH)
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        default_missing_value = nfd::DEFAULT_NFD_FEATURES_FILE
+    )]
+    nfd_features_file: Option<PathBuf>,
+
+    /// seconds between refreshes of node annotations and NFD feature files
+    #[arg(long, default_value_t = 60)]
+    node_info_interval_secs: u64,
+
+    /// also advertise RDMA NICs co-located with GPUs as a companion GPUDirect resource
+    #[arg(long)]
+    gpudirect: bool,
+
+    /// resource name for GPUDirect RDMA devices (must match the mofed/ib CDI kind)
+    #[arg(long, default_value = DEFAULT_GPUDIRECT_RESOURCE_NAME)]
+    gpudirect_resource_name: String,
+
+    /// unix domain socket name for the GPUDirect resource
+    #[arg(long, default_value = DEFAULT_GPUDIRECT_SOCKET_NAME)]
+    gpudirect_socket_name: String,
+
+    /// inject the RDMA NICs on the NUMA nodes of the allocated GPUs into every GPU allocation,
+    /// using the mofed/ib CDI kind named by --gpudirect-resource-name
+    #[arg(long)]
+    gpudirect_inject: bool,
+
+    /// also advertise VFIO-bound SR-IOV virtual functions of the GPUs as a separate resource
+    #[arg(long)]
+    sriov: bool,
+
+    /// resource name for SR-IOV GPU virtual functions
+    #[arg(long, default_value = DEFAULT_SRIOV_RESOURCE_NAME)]
+    sriov_resource_name: String,
+
+    /// unix domain socket name for the SR-IOV resource
+    #[arg(long, default_value = DEFAULT_SRIOV_SOCKET_NAME)]
+    sriov_socket_name: String,
+
+    /// seconds between NVLink fabric (nvidia-fabricmanager) checks gating GPU health; 0 disables
+    #[arg(long, default_value_t = 30)]
+    fabric_check_interval_secs: u64,
+
+    /// how to hand IMEX channels (/dev/nvidia-caps-imex-channels) to workloads
+    #[arg(long, value_enum, default_value_t = imex::Mode::Off)]
+    imex_channels: imex::Mode,
+
+    /// resource name for IMEX channels (must match the IMEX channel CDI kind)
+    #[arg(long, default_value = imex::DEFAULT_IMEX_RESOURCE_NAME)]
+    imex_resource_name: String,
+
+    /// unix domain socket name for the IMEX channel resource
+    #[arg(long, default_value = imex::DEFAULT_IMEX_SOCKET_NAME)]
+    imex_socket_name: String,
+
+    /// keep a NodeResourceTopology object describing per-NUMA-zone device availability up to date
+    #[arg(long)]
+    export_nrt: bool,
+
+    /// seconds between NodeResourceTopology updates
+    #[arg(long, default_value_t = 10)]
+    nrt_interval_secs: u64,
+
+    /// kubelet PodResources API socket, used to track which devices are in use
+    #[arg(long, default_value = podresources::DEFAULT_POD_RESOURCES_SOCKET)]
+    pod_resources_socket: PathBuf,
+
+    /// directory holding all state the plugin writes outside the kubelet directory
+    #[arg(long, default_value = DEFAULT_STATE_DIR)]
+    state_dir: PathBuf,
+
+    /// unix domain socket for the local admin API [default: <state-dir>/admin.sock]
+    #[arg(long)]
+    admin_socket: Option<PathBuf>,
+
+    /// serve a read-only API mapping device IDs to GPU UUIDs, PCI bus IDs, MIG devices and pods
+    #[arg(long)]
+    device_map: bool,
+
+    /// unix domain socket for the device map API [default: <state-dir>/devices.sock]
+    #[arg(long)]
+    device_map_socket: Option<PathBuf>,
+
+    /// user IDs allowed to call the device map socket (checked via SO_PEERCRED)
+    #[arg(long = "device-map-allowed-uid", default_values_t = [0])]
+    device_map_allowed_uids: Vec<u32>,
+
+    /// group IDs allowed to call the device map socket
+    #[arg(long = "device-map-allowed-gid")]
+    device_map_allowed_gids: Vec<u32>,
+
+    /// file recording devices marked for retirement [default: <state-dir>/retirements.json]
+    #[arg(long)]
+    retirement_state_file: Option<PathBuf>,
+
+    /// executable run with RESOURCE_NAME and DEVICE_ID set once a device has been retired
+    #[arg(long)]
+    retirement_hook: Option<PathBuf>,
+
+    /// switch to this user ID, without any capabilities, once sockets are bound and registered;
+    /// the plugin exits to be restarted as root when kubelet restarts. Directories outside
+    /// --state-dir (e.g. --nfd-features-file) must already be writable for the group.
+    #[arg(long)]
+    run_as_uid: Option<u32>,
+
+    /// group ID to switch to (defaults to --run-as-uid)
+    #[arg(long, requires = "run_as_uid")]
+    run_as_gid: Option<u32>,
+
+    /// user IDs allowed to call the plugin and admin sockets (checked via SO_PEERCRED)
+    #[arg(long = "allowed-peer-uid", default_values_t = [0])]
+    allowed_peer_uids: Vec<u32>,
+
+    /// group IDs allowed to call the plugin and admin sockets
+    #[arg(long = "allowed-peer-gid")]
+    allowed_peer_gids: Vec<u32>,
+
+    /// maximum number of gRPC requests handled at once per socket
+    #[arg(long, default_value_t = rpc::RpcLimits::default().max_concurrent)]
+    max_concurrent_rpcs: usize,
+
+    /// seconds before a gRPC call (e.g. Allocate) is abandoned
+    #[arg(long, default_value_t = rpc::RpcLimits::default().timeout.as_secs())]
+    rpc_timeout_secs: u64,
+
+    /// seconds to keep retrying transient startup failures (kubelet not up yet, driver still
+    /// loading) before exiting; configuration errors exit immediately
+    #[arg(long, default_value_t = 300)]
+    startup_retry_secs: u64,
+
+    /// take over from an instance already running on this node without dropping capacity: serve
+    /// and register first, then ask it to stop through the admin socket
+    #[arg(long)]
+    upgrade: bool,
+
+    /// file created once every resource is registered with kubelet and removed on shutdown, checked
+    /// by the `ready` subcommand (should be private to this instance, e.g. on an emptyDir)
+    #[arg(long)]
+    ready_file: Option<PathBuf>,
+
+    /// take over plugin and admin sockets even if another instance is still answering on them
+    #[arg(long)]
+    force: bool,
+
+    /// skip the Landlock/seccomp sandbox (for debugging)
+    #[arg(long)]
+    no_sandbox: bool,
+
+    /// directories searched for CDI specs
+    #[arg(long = "cdi-spec-dir", default_values = versions::DEFAULT_CDI_SPEC_DIRS)]
+    cdi_spec_dirs: Vec<PathBuf>,
+
+    /// what to do at startup if the container runtime does not have CDI injection enabled
+    #[arg(long, value_enum, default_value_t = preflight::Policy::Warn)]
+    cdi_preflight: preflight::Policy,
+
+    /// containerd configuration checked for CDI support
+    #[arg(long, default_value = preflight::DEFAULT_CONTAINERD_CONFIG)]
+    containerd_config: PathBuf,
+
+    /// CRI-O configuration directory checked for CDI support
+    #[arg(long, default_value = preflight::DEFAULT_CRIO_CONFIG_DIR)]
+    crio_config_dir: PathBuf,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Args {
+    // Whether allocations rely on the container runtime injecting CDI devices.
+    fn uses_cdi(&self) -> bool {
+        self.mode == Mode::Cdi || self.vfio_cdi
+    }
+
+    fn admin_socket(&self) -> PathBuf {
+        self.admin_socket
+            .clone()
+            .unwrap_or_else(|| self.state_dir.join(admin::ADMIN_SOCKET_NAME))
+    }
+
+    fn device_map_socket(&self) -> PathBuf {
+        self.device_map_socket
+            .clone()
+            .unwrap_or_else(|| self.state_dir.join(devicemap::DEVICE_MAP_SOCKET_NAME))
+    }
+
+    fn handoff_token_file(&self) -> PathBuf {
+        self.state_dir.join(admin::HANDOFF_TOKEN_FILE_NAME)
+    }
+
+    fn retirement_state_file(&self) -> PathBuf {
+        self.retirement_state_file
+            .clone()
+            .unwrap_or_else(|| self.state_dir.join(retirement::RETIREMENT_STATE_FILE_NAME))
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    /// inject /dev/nvidia* devices through CDI (runc/crun style runtimes)
+    Cdi,
+    /// pass vfio-pci bound GPUs through to VM-based runtimes such as Kata
+    Vfio,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Inspect plugin and kubelet state
+    Debug {
+        #[command(subcommand)]
+        command: DebugCommand,
+    },
+    /// Talk to a running plugin through its admin socket
+    Admin {
+        #[command(subcommand)]
+        command: AdminCommand,
+    },
+    /// Check that the driver, devices, CDI specs and container runtime are ready for the plugin
+    Validate,
+    /// Show the GPU connectivity matrix and NUMA affinities the plugin sees, like nvidia-smi topo -m
+    Topo,
+    /// Exit successfully once the instance writing --ready-file has registered, for readiness probes
+    Ready,
+    /// Collect diagnostics into a tarball for attaching to bug reports
+    SupportBundle {
+        /// tarball to write [default: nvidia-cdi-device-plugin-support-<timestamp>.tar.gz]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AdminCommand {
+    /// Stop offering a device to new pods and remove it once its last pod has terminated
+    Retire {
+        /// device ID as advertised to kubelet, e.g. nvidia.com/gpu=3
+        device_id: String,
+
+        /// resource the device belongs to (defaults to --resource-name)
+        #[arg(long = "resource")]
+        resource_name: Option<String>,
+    },
+    /// List devices marked for retirement
+    Retirements,
+}
+
+#[derive(Subcommand, Debug)]
+enum DebugCommand {
+    /// Show which pods own our devices according to kubelet's device manager checkpoint
+    Checkpoint {
+        /// checkpoint file (defaults to kubelet_internal_checkpoint in the kubelet dir)
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Show the NVLink fabric state of each GPU and whether it gates device health
+    Fabric,
+    /// Show the device map served by a running plugin (see --device-map)
+    Devices {
+        /// only show devices of this resource
+        #[arg(long)]
+        resource_name: Option<String>,
+    },
+}
+
+fn topology_info(numa_node: Option<i64>) -> Option<k8s::TopologyInfo> {
+    numa_node.map(|id| k8s::TopologyInfo {
+        nodes: vec![k8s::NumaNode { id }],
+    })
+}
+
+fn numa_node(dev: &k8s::Device) -> Option<i64> {
+    dev.topology.as_ref()?.nodes.first().map(|node| node.id)
+}
+
+// Device minors of the GPU device nodes, in the order their device IDs are numbered.
+fn device_minors() -> anyhow::Result<Vec<Option<u32>>> {
+    Ok(glob(DEVICE_GLOB)?
+        .flatten()
+        .map(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.trim_start_matches("nvidia").parse::<u32>().ok())
+        })
+        .collect())
 }
 
 fn discover_devices(resource_name: &str) -> anyhow::Result<BTreeMap<String, k8s::Device>> {
     let mut devs = BTreeMap::new();
     let pattern = DEVICE_GLOB;
+    let gpus = gpu::discover_gpus();
 
-    for (idx, _path) in glob(pattern)?.flatten().enumerate() {
+    for (idx, minor) in device_minors()?.into_iter().enumerate() {
         let id = format!("{resource_name}={idx}");
+        let numa_node = gpus
+            .iter()
+            .find(|gpu| Some(gpu.minor) == minor)
+            .and_then(|gpu| gpu.numa_node);
         devs.insert(
             id.clone(),
             k8s::Device {
                 id,
                 health: "Healthy".to_string(),
-                topology: None,
+                topology: topology_info(numa_node),
             },
         );
     }
@@ -71,20 +437,91 @@ fn discover_devices(resource_name: &str) -> anyhow::Result<BTreeMap<String, k8s:
     Ok(devs)
 }
 
+// Socket an alias or per-model resource is served on, e.g. `nvidia-cdi-nvidia.com_a100.sock`.
+fn resource_socket_name(resource_name: &str) -> String {
+    format!("nvidia-cdi-{}.sock", resource_name.replace('/', "_"))
+}
+
+type VfioDevices = BTreeMap<String, vfio::VfioDevice>;
+
+/// Devices currently advertised for one resource. ListAndWatch streams re-send the list whenever
+/// it changes.
+type SharedDevices = Arc<store::DeviceStore>;
+
 #[derive(Clone)]
 struct NvidiaCdiDevicePlugin {
     resource_name: String,
-    devices: BTreeMap<String, k8s::Device>,
+    devices: SharedDevices,
+    // Devices passed through as VFIO groups rather than injected by CDI name.
+    vfio: VfioDevices,
+    vfio_cdi: bool,
+    // CDI devices injected into every allocation, e.g. IMEX channels.
+    extra_cdi_devices: Vec<String>,
+    // CDI devices injected alongside allocated devices on the same NUMA node, e.g. GPUDirect NICs.
+    numa_cdi_devices: BTreeMap<Option<i64>, Vec<String>>,
+    peers: peercred::PeerPolicy,
+    limits: rpc::RpcLimits,
+    // Set when the same devices are also advertised under other resource names.
+    occupancy: Option<Arc<occupancy::Occupancy>>,
     shutdown: watch::Receiver<bool>,
 }
 
 impl NvidiaCdiDevicePlugin {
-    fn new(resource_name: String, shutdown: watch::Receiver<bool>) -> anyhow::Result<Self> {
-        Ok(Self {
-            devices: discover_devices(&resource_name)?,
+    fn new(
+        resource_name: String,
+        devices: BTreeMap<String, k8s::Device>,
+        shutdown: watch::Receiver<bool>,
+    ) -> Self {
+        Self {
             resource_name,
+            devices: Arc::new(store::DeviceStore::new(devices)),
+            vfio: BTreeMap::new(),
+            vfio_cdi: false,
+            extra_cdi_devices: Vec::new(),
+            numa_cdi_devices: BTreeMap::new(),
+            peers: peercred::PeerPolicy::default(),
+            limits: rpc::RpcLimits::default(),
+            occupancy: None,
             shutdown,
-        })
+        }
+    }
+
+    fn with_vfio_devices(mut self, vfio: VfioDevices, inject_cdi: bool) -> Self {
+        self.vfio = vfio;
+        self.vfio_cdi = inject_cdi;
+        self
+    }
+
+    fn with_extra_cdi_devices(mut self, names: Vec<String>) -> Self {
+        self.extra_cdi_devices = names;
+        self
+    }
+
+    fn with_numa_cdi_devices(mut self, names: BTreeMap<Option<i64>, Vec<String>>) -> Self {
+        self.numa_cdi_devices = names;
+        self
+    }
+
+    fn with_peer_policy(mut self, peers: peercred::PeerPolicy) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    fn with_rpc_limits(mut self, limits: rpc::RpcLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    fn with_occupancy(mut self, occupancy: Arc<occupancy::Occupancy>) -> Self {
+        self.occupancy = Some(occupancy);
+        self
+    }
+}
+
+fn plugin_options() -> k8s::DevicePluginOptions {
+    k8s::DevicePluginOptions {
+        pre_start_required: false,
+        get_preferred_allocation_available: true,
     }
 }
 
@@ -94,10 +531,7 @@ impl k8s::device_plugin_server::DevicePlugin for NvidiaCdiDevicePlugin {
         &self,
         _request: Request<k8s::Empty>,
     ) -> Result<Response<k8s::DevicePluginOptions>, Status> {
-        Ok(Response::new(k8s::DevicePluginOptions {
-            pre_start_required: false,
-            get_preferred_allocation_available: false,
-        }))
+        Ok(Response::new(plugin_options()))
     }
 
     type ListAndWatchStream = ReceiverStream<Result<k8s::ListAndWatchResponse, Status>>;
@@ -106,31 +540,60 @@ impl k8s::device_plugin_server::DevicePlugin for NvidiaCdiDevicePlugin {
         &self,
         _request: Request<k8s::Empty>,
     ) -> Result<Response<Self::ListAndWatchStream>, Status> {
-        let devices: Vec<k8s::Device> = self.devices.values().cloned().collect();
+        let mut updates = self.devices.subscribe();
+        let snapshot = updates.borrow_and_update().clone();
         println!(
             "ListAndWatch for {} advertising {} devices",
             self.resource_name,
-            devices.len()
+            snapshot.len()
         );
         let (tx, rx) = mpsc::channel(1);
 
-        tx.send(Ok(k8s::ListAndWatchResponse { devices }))
+        tx.send(Ok(snapshot.list_and_watch_response().clone()))
             .await
             .map_err(|_| Status::internal("failed to send initial device list"))?;
+        let mut sent = snapshot.generation();
+        drop(snapshot);
 
-        // Keep the stream open until shutdown, mimicking the Go plugin's blocking behavior.
+        // Keep the stream open until shutdown, mimicking the Go plugin's blocking behavior, and
+        // re-send the full list whenever the advertised devices change.
         let mut shutdown = self.shutdown.clone();
-        let tx_hold = tx.clone();
+        let resource_name = self.resource_name.clone();
         tokio::spawn(async move {
             loop {
                 if *shutdown.borrow() {
                     break;
                 }
-                if shutdown.changed().await.is_err() {
-                    break;
+                select! {
+                    changed = updates.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                        // Bursts of updates coalesce into a single resend of the latest generation.
+                        let snapshot = updates.borrow_and_update().clone();
+                        if snapshot.generation() == sent {
+                            continue;
+                        }
+                        println!(
+                            "ListAndWatch for {resource_name} advertising {} devices ({} unhealthy, generation {})",
+                            snapshot.len(),
+                            snapshot.unhealthy(),
+                            snapshot.generation()
+                        );
+                        let response = snapshot.list_and_watch_response().clone();
+                        sent = snapshot.generation();
+                        drop(snapshot);
+                        if tx.send(Ok(response)).await.is_err() {
+                            break;
+                        }
+                    }
+                    changed = shutdown.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                    }
                 }
             }
-            drop(tx_hold);
         });
 
         Ok(Response::new(ReceiverStream::new(rx)))
@@ -143,30 +606,88 @@ impl k8s::device_plugin_server::DevicePlugin for NvidiaCdiDevicePlugin {
         let mut container_responses =
             Vec::with_capacity(request.get_ref().container_requests.len());
 
+        let snapshot = self.devices.snapshot();
         for creq in &request.get_ref().container_requests {
             let mut cdi_devices = Vec::with_capacity(creq.devices_ids.len());
+            let mut vfio_devices = Vec::new();
 
             for dev_id in &creq.devices_ids {
-                if !self.devices.contains_key(dev_id) {
+                if !snapshot.contains(dev_id) {
                     return Err(Status::invalid_argument(format!(
                         "unknown device ID {dev_id}"
                     )));
                 }
 
+                if let Some(vfio_dev) = self.vfio.get(dev_id) {
+                    vfio_devices.push(vfio_dev);
+                    if !self.vfio_cdi {
+                        continue;
+                    }
+                }
+
                 cdi_devices.push(k8s::CdiDevice {
                     name: dev_id.clone(),
                 });
             }
 
+            cdi_devices.extend(
+                self.extra_cdi_devices
+                    .iter()
+                    .map(|name| k8s::CdiDevice { name: name.clone() }),
+            );
+            if !self.numa_cdi_devices.is_empty() {
+                let nodes: BTreeSet<Option<i64>> = creq
+                    .devices_ids
+                    .iter()
+                    .filter_map(|id| snapshot.devices().get(id))
+                    .map(numa_node)
+                    .collect();
+                cdi_devices.extend(
+                    nodes
+                        .iter()
+                        .filter_map(|node| self.numa_cdi_devices.get(node))
+                        .flatten()
+                        .map(|name| k8s::CdiDevice { name: name.clone() }),
+                );
+            }
+
+            let mut envs = HashMap::new();
+            let mut devices = vec![];
+            if !vfio_devices.is_empty() {
+                let bus_ids: Vec<&str> = vfio_devices
+                    .iter()
+                    .map(|dev| dev.pci_bus_id.as_str())
+                    .collect();
+                envs.insert(
+                    vfio::pci_resource_env(&self.resource_name),
+                    bus_ids.join(","),
+                );
+                devices = vfio::device_specs(vfio_devices);
+            }
+
             container_responses.push(k8s::ContainerAllocateResponse {
-                envs: Default::default(),
+                envs,
                 mounts: vec![],
-                devices: vec![],
+                devices,
                 annotations: Default::default(),
                 cdi_devices,
             });
         }
 
+        // Claimed for all containers at once, so that a conflict in one leaves no holds behind
+        // for the others.
+        if let Some(occupancy) = &self.occupancy {
+            let device_ids: Vec<String> = request
+                .get_ref()
+                .container_requests
+                .iter()
+                .flat_map(|creq| creq.devices_ids.iter().cloned())
+                .collect();
+            occupancy
+                .claim(&self.resource_name, &device_ids)
+                .map_err(|err| Status::failed_precondition(err.to_string()))?;
+        }
+
         Ok(Response::new(k8s::AllocateResponse {
             container_responses,
         }))
@@ -180,10 +701,14 @@ impl k8s::device_plugin_server::DevicePlugin for NvidiaCdiDevicePlugin {
             container_responses: Vec::new(),
         };
 
+        let snapshot = self.devices.snapshot();
         for creq in &request.get_ref().container_requests {
-            let available = &creq.available_device_i_ds;
-            let size = creq.allocation_size as usize;
-            let chosen = available.iter().take(size).cloned().collect();
+            let chosen = preferred::preferred_devices(
+                snapshot.devices(),
+                &creq.available_device_i_ds,
+                &creq.must_include_device_i_ds,
+                creq.allocation_size as usize,
+            );
 
             out.container_responses
                 .push(k8s::ContainerPreferredAllocationResponse {
@@ -205,17 +730,16 @@ impl k8s::device_plugin_server::DevicePlugin for NvidiaCdiDevicePlugin {
 async fn start_device_plugin_server(
     plugin: NvidiaCdiDevicePlugin,
     socket_path: PathBuf,
+    force: bool,
 ) -> anyhow::Result<JoinHandle<()>> {
-    if socket_path.exists() {
-        std::fs::remove_file(&socket_path)?;
-    }
-
-    let uds = UnixListener::bind(&socket_path)?;
+    let uds = socket::bind(&socket_path, force)?;
     let incoming = UnixListenerStream::new(uds);
-    let service = k8s::device_plugin_server::DevicePluginServer::new(plugin);
+    let peers = plugin.peers.clone();
+    let mut server = plugin.limits.server();
+    let service = k8s::device_plugin_server::DevicePluginServer::with_interceptor(plugin, peers);
 
     let handle = tokio::spawn(async move {
-        if let Err(err) = Server::builder()
+        if let Err(err) = server
             .add_service(service)
             .serve_with_incoming(incoming)
             .await
@@ -244,19 +768,26 @@ async fn wait_for_socket(socket_path: &Path, timeout: Duration) -> anyhow::Resul
     }
 }
 
+async fn unix_channel(socket_path: PathBuf) -> anyhow::Result<Channel> {
+    let channel = Endpoint::try_from("http://[::]:50051")?
+        .connect_with_connector(service_fn(move |_| {
+            let path = socket_path.clone();
+            async move { UnixStream::connect(path).await.map(TokioIo::new) }
+        }))
+        .await?;
+    Ok(channel)
+}
+
 async fn register_with_kubelet(
     kubelet_dir: &str,
     socket_name: &str,
     resource_name: &str,
 ) -> anyhow::Result<()> {
     let kubelet_socket = Path::new(kubelet_dir).join("kubelet.sock");
-
-    let channel = Endpoint::try_from("http://[::]:50051")?
-        .connect_with_connector(service_fn(move |_| {
-            let path = kubelet_socket.clone();
-            async move { UnixStream::connect(path).await.map(TokioIo::new) }
-        }))
-        .await?;
+    if let Err(err) = std::fs::metadata(&kubelet_socket) {
+        return Err(startup::missing(&kubelet_socket, err));
+    }
+    let channel = unix_channel(kubelet_socket).await?;
 
     let mut client = k8s::registration_client::RegistrationClient::new(channel);
 
@@ -264,10 +795,7 @@ async fn register_with_kubelet(
         version: DEVICE_PLUGIN_VERSION.to_string(),
         endpoint: socket_name.to_string(),
         resource_name: resource_name.to_string(),
-        options: Some(k8s::DevicePluginOptions {
-            pre_start_required: false,
-            get_preferred_allocation_available: false,
-        }),
+        options: Some(plugin_options()),
     };
 
     client.register(req).await?;
@@ -281,6 +809,7 @@ async fn maintain_registration(
     plugin: NvidiaCdiDevicePlugin,
     socket_path: PathBuf,
     server_handle: Arc<Mutex<JoinHandle<()>>>,
+    lost_registration: Arc<Notify>,
     mut shutdown: watch::Receiver<bool>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
@@ -289,13 +818,20 @@ async fn maintain_registration(
                 break;
             }
 
-            // If kubelet cleaned up the socket, restart the gRPC server to re-bind the path.
-            if !socket_path.exists() {
+            // If kubelet cleaned up the socket or the server died, restart the gRPC server to
+            // re-bind the path. Without root the kubelet directory is off limits, so start over.
+            let server_stopped = server_handle.lock().await.is_finished();
+            if !socket_path.exists() || server_stopped {
+                if !privileges::privileged() {
+                    eprintln!("device plugin server for {resource_name} is gone");
+                    lost_registration.notify_one();
+                    break;
+                }
                 {
                     let handle = server_handle.lock().await;
                     handle.abort();
                 }
-                match start_device_plugin_server(plugin.clone(), socket_path.clone()).await {
+                match start_device_plugin_server(plugin.clone(), socket_path.clone(), false).await {
                     Ok(new_handle) => {
                         let mut guard = server_handle.lock().await;
                         *guard = new_handle;
@@ -306,8 +842,11 @@ async fn maintain_registration(
                 }
             }
 
-            if let Err(err) =
-                register_with_kubelet(&kubelet_dir, &socket_name, &resource_name).await
+            // Without root kubelet.sock cannot be reached, but then nothing changed since the
+            // registration at startup.
+            if privileges::privileged()
+                && let Err(err) =
+                    register_with_kubelet(&kubelet_dir, &socket_name, &resource_name).await
             {
                 eprintln!("registration with kubelet failed: {err}");
             }
@@ -324,50 +863,1074 @@ async fn maintain_registration(
     })
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+struct PluginTasks {
+    server_handle: Arc<Mutex<JoinHandle<()>>>,
+    reg_task: JoinHandle<()>,
+}
+
+impl PluginTasks {
+    async fn abort(&self) {
+        self.server_handle.lock().await.abort();
+        self.reg_task.abort();
+    }
+}
+
+async fn serve_plugin(
+    plugin: NvidiaCdiDevicePlugin,
+    kubelet_dir: &str,
+    socket_name: &str,
+    force: bool,
+    supervisor: &supervisor::Supervisor,
+    lost_registration: Arc<Notify>,
+    shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<PluginTasks> {
+    let socket_path = Path::new(kubelet_dir).join(socket_name);
+    let resource_name = plugin.resource_name.clone();
+
+    let server = start_device_plugin_server(plugin.clone(), socket_path.clone(), force).await?;
+    let registered = async {
+        wait_for_socket(&socket_path, Duration::from_secs(5)).await?;
+        register_with_kubelet(kubelet_dir, socket_name, &resource_name).await
+    };
+    if let Err(err) = registered.await {
+        // Release the socket so that a retry can bind it again.
+        server.abort();
+        return Err(err);
+    }
+    let server_handle = Arc::new(Mutex::new(server));
+
+    let kubelet_dir = kubelet_dir.to_string();
+    let socket_name = socket_name.to_string();
+    let handle = server_handle.clone();
+    let reg_task = supervisor.spawn(format!("{resource_name} registration"), move || {
+        maintain_registration(
+            kubelet_dir.clone(),
+            socket_name.clone(),
+            resource_name.clone(),
+            plugin.clone(),
+            socket_path.clone(),
+            handle.clone(),
+            lost_registration.clone(),
+            shutdown.clone(),
+        )
+        .map(Ok)
+    });
+
+    Ok(PluginTasks {
+        server_handle,
+        reg_task,
+    })
+}
+
+fn discover_resource_devices(
+    args: &Args,
+) -> anyhow::Result<(BTreeMap<String, k8s::Device>, VfioDevices)> {
+    match args.mode {
+        Mode::Cdi => Ok((discover_devices(&args.resource_name)?, BTreeMap::new())),
+        Mode::Vfio => Ok(vfio::advertise(
+            &args.resource_name,
+            vfio::discover_vfio_gpus(),
+        )),
+    }
+}
+
+// Driver view of the GPU behind each CDI device ID.
+fn discover_device_gpus(resource_name: &str) -> anyhow::Result<BTreeMap<String, gpu::GpuInfo>> {
+    let gpus = gpu::discover_gpus();
+    let mut out = BTreeMap::new();
+    for (idx, minor) in device_minors()?.into_iter().enumerate() {
+        if let Some(gpu) = gpus.iter().find(|gpu| Some(gpu.minor) == minor) {
+            out.insert(format!("{resource_name}={idx}"), gpu.clone());
+        }
+    }
+    Ok(out)
+}
+
+// GPU model of each device ID, for the --mixed-models check.
+fn discover_device_models(
+    args: &Args,
+    vfio_devices: &VfioDevices,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    match args.mode {
+        Mode::Cdi => Ok(discover_device_gpus(&args.resource_name)?
+            .into_iter()
+            .map(|(id, gpu)| (id, gpu.model))
+            .collect()),
+        // The driver does not see GPUs bound to vfio-pci, so fall back to their PCI device ID.
+        Mode::Vfio => Ok(vfio_devices
+            .iter()
+            .filter_map(|(id, dev)| Some((id.clone(), gpu::pci_model(&dev.pci_bus_id)?)))
+            .collect()),
+    }
+}
+
+async fn run_command(command: &Command, args: &Args) -> anyhow::Result<()> {
+    match command {
+        Command::Debug {
+            command: DebugCommand::Checkpoint { file },
+        } => {
+            let path = file.clone().unwrap_or_else(|| {
+                Path::new(&args.kubelet_dir).join(checkpoint::KUBELET_CHECKPOINT_FILE)
+            });
+            let (devices, _) = discover_resource_devices(args)?;
+            checkpoint::print_checkpoint(&path, &args.resource_name, &devices)
+        }
+        Command::Debug {
+            command: DebugCommand::Fabric,
+        } => {
+            let gpus = discover_device_gpus(&args.resource_name)?;
+            let fabric = fabric::query().await?;
+            println!(
+                "{:<32} {:<14} {:<14} {:<14} HEALTH",
+                "DEVICE", "PCI BUS ID", "STATE", "STATUS"
+            );
+            for (id, gpu) in &gpus {
+                let (state, status, health) = match fabric.get(&gpu.pci_bus_id) {
+                    Some(info) => (
+                        info.state.as_str(),
+                        info.status.as_str(),
+                        info.problem()
+                            .map_or("ok".to_string(), |reason| format!("unhealthy: {reason}")),
+                    ),
+                    None => ("-", "-", "unknown to nvidia-smi".to_string()),
+                };
+                println!(
+                    "{:<32} {:<14} {:<14} {:<14} {health}",
+                    id, gpu.pci_bus_id, state, status
+                );
+            }
+            Ok(())
+        }
+        Command::Debug {
+            command: DebugCommand::Devices { resource_name },
+        } => devicemap::print_devices(args.device_map_socket(), resource_name.as_deref()).await,
+        Command::Admin {
+            command:
+                AdminCommand::Retire {
+                    device_id,
+                    resource_name,
+                },
+        } => {
+            let resource_name = resource_name.as_deref().unwrap_or(&args.resource_name);
+            admin::retire(args.admin_socket(), resource_name, device_id).await
+        }
+        Command::Admin {
+            command: AdminCommand::Retirements,
+        } => admin::list_retirements(args.admin_socket()).await,
+        Command::Validate => preflight::print_report(&validation_checks(args)?),
+        Command::Topo => print_topology(args),
+        Command::Ready => match &args.ready_file {
+            Some(path) if path.exists() => Ok(()),
+            Some(path) => anyhow::bail!("not ready: {} does not exist", path.display()),
+            None => anyhow::bail!("--ready-file is not set"),
+        },
+        Command::SupportBundle { output } => write_support_bundle(args, output.as_deref()),
+    }
+}
+
+fn print_topology(args: &Args) -> anyhow::Result<()> {
+    let (devices, vfio_devices) = discover_resource_devices(args)?;
+    let bus_ids: BTreeMap<String, String> = match args.mode {
+        Mode::Cdi => discover_device_gpus(&args.resource_name)?
+            .into_iter()
+            .map(|(id, gpu)| (id, gpu.pci_bus_id))
+            .collect(),
+        Mode::Vfio => vfio_devices
+            .into_iter()
+            .map(|(id, dev)| (id, dev.pci_bus_id))
+            .collect(),
+    };
+    let gpus: Vec<(&String, &str, Option<i64>)> = devices
+        .iter()
+        .map(|(id, dev)| {
+            let bus_id = bus_ids.get(id).map_or("-", String::as_str);
+            (id, bus_id, numa_node(dev))
+        })
+        .collect();
+    let nvlinks = topology::nvlinks();
+
+    print!("{:<8}", "");
+    for idx in 0..gpus.len() {
+        print!("{:<7}", format!("GPU{idx}"));
+    }
+    println!("{:<15} {:<14} DEVICE ID", "NUMA Affinity", "PCI BUS ID");
+    for (row, (id, bus_id, numa)) in gpus.iter().enumerate() {
+        print!("{:<8}", format!("GPU{row}"));
+        for (col, (_, other_bus_id, other_numa)) in gpus.iter().enumerate() {
+            let link = if row == col {
+                topology::Link::Own
+            } else if let Some(count) = nvlinks.get(&(bus_id.to_string(), other_bus_id.to_string()))
+            {
+                topology::Link::NvLink(*count)
+            } else {
+                topology::pcie_link(bus_id, other_bus_id, *numa, *other_numa)
+            };
+            print!("{:<7}", link.to_string());
+        }
+        let numa = numa.map_or("N/A".to_string(), |node| node.to_string());
+        println!("{numa:<15} {bus_id:<14} {id}");
+    }
+    println!();
+    println!("Legend:");
+    println!();
+    println!("{}", topology::LEGEND);
+    println!();
+    println!(
+        "note: the plugin allocates by NUMA node only; NVLink and PCIe links do not affect which GPUs a pod gets"
+    );
+    if nvlinks.is_empty() {
+        println!();
+        println!("no NVLink information (nvidia-smi unavailable or no NVLinks)");
+    }
+    Ok(())
+}
+
+fn validation_checks(args: &Args) -> anyhow::Result<Vec<preflight::Check>> {
+    let mut checks = Vec::new();
+    if args.mode == Mode::Cdi {
+        checks.push(preflight::driver_check());
+    }
+    let (devices, _) = discover_resource_devices(args)?;
+    checks.push(preflight::devices_check(devices.len()));
+    if args.uses_cdi() {
+        checks.push(preflight::cdi_spec_check(
+            &args.resource_name,
+            &args.cdi_spec_dirs,
+        ));
+        checks.push(preflight::runtime_check(
+            &args.containerd_config,
+            &args.crio_config_dir,
+        ));
+    }
+    Ok(checks)
+}
+
+// What the plugin would advertise right now, as seen by discovery.
+fn discovery_report(args: &Args) -> anyhow::Result<String> {
+    let (devices, vfio_devices) = discover_resource_devices(args)?;
+    let mut out = format!("{}: {} devices\n", args.resource_name, devices.len());
+    for (id, dev) in &devices {
+        out.push_str(&format!(
+            "  {id} health={} numa={:?}",
+            dev.health,
+            numa_node(dev)
+        ));
+        if let Some(vfio) = vfio_devices.get(id) {
+            out.push_str(&format!(
+                " pci={} iommu_group={}",
+                vfio.pci_bus_id, vfio.iommu_group
+            ));
+        }
+        out.push('\n');
+    }
+    out.push_str("\ngpus:\n");
+    for gpu in gpu::discover_gpus() {
+        out.push_str(&format!(
+            "  minor={} pci={} model={:?} numa={:?}\n",
+            gpu.minor, gpu.pci_bus_id, gpu.model, gpu.numa_node
+        ));
+    }
+    out.push_str(&format!(
+        "\nimex channels: {:?}\n",
+        imex::discover_channels()
+    ));
+    let versions = versions::detect_versions(&args.resource_name, &args.cdi_spec_dirs);
+    out.push_str(&format!("\nversions: {versions:?}\n"));
+    Ok(out)
+}
+
+fn write_support_bundle(args: &Args, output: Option<&Path>) -> anyhow::Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let name = format!("nvidia-cdi-device-plugin-support-{timestamp}");
+    let path = output.map_or_else(
+        || PathBuf::from(format!("{name}.tar.gz")),
+        Path::to_path_buf,
+    );
+
+    // Identify the node only by placeholder.
+    let mut replacements = Vec::new();
+    if let Some(node_name) = &args.node_name {
+        replacements.push((node_name.clone(), "<node-name>"));
+    }
+    if let Ok(hostname) = std::fs::read_to_string("/proc/sys/kernel/hostname") {
+        replacements.push((hostname.trim().to_string(), "<hostname>"));
+    }
+
+    let mut bundle = bundle::Bundle::create(&path, &name, replacements)?;
+    bundle.add("config.txt", &format!("{args:#?}\n"))?;
+    bundle.add_result(
+        "validate.txt",
+        validation_checks(args).map(|checks| preflight::format_report(&checks)),
+    )?;
+    bundle.add_result("discovery.txt", discovery_report(args))?;
+    bundle.add_file("state/retirements.json", &args.retirement_state_file())?;
+    bundle.add_result(
+        "state/kubelet-dir.txt",
+        std::fs::read_dir(&args.kubelet_dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| format!("{}\n", entry.file_name().to_string_lossy()))
+                    .collect()
+            })
+            .map_err(Into::into),
+    )?;
+    let mut resource_names = vec![args.resource_name.clone()];
+    resource_names.extend(args.alias_resource_names.iter().cloned());
+    bundle.add_result(
+        "kubelet-checkpoint.txt",
+        checkpoint::excerpt(
+            &Path::new(&args.kubelet_dir).join(checkpoint::KUBELET_CHECKPOINT_FILE),
+            &resource_names,
+        ),
+    )?;
+    for dir in &args.cdi_spec_dirs {
+        for spec in versions::cdi_spec_files(dir) {
+            let file_name = spec.file_name().unwrap_or_default().to_string_lossy();
+            bundle.add_file(&format!("cdi{}/{file_name}", dir.display()), &spec)?;
+        }
+    }
+    bundle.add_result(
+        "dmesg-xid.txt",
+        bundle::run("dmesg", &[]).map(|log| bundle::xid_lines(&log)),
+    )?;
+    bundle.add_command("nvidia-smi.txt", "nvidia-smi", &["-q"])?;
+    bundle.add_command("nvidia-smi-topo.txt", "nvidia-smi", &["topo", "-m"])?;
+    bundle.finish()?;
+
+    println!("wrote {}", path.display());
+    Ok(())
+}
+
+// Directories only the plugin writes to: the state directory and wherever its files were moved.
+fn owned_dirs(args: &Args) -> Vec<PathBuf> {
+    let mut dirs = vec![args.state_dir.clone()];
+    dirs.extend(sandbox::parent_dir(&args.admin_socket()));
+    dirs.extend(sandbox::parent_dir(&args.retirement_state_file()));
+    if args.device_map {
+        dirs.extend(sandbox::parent_dir(&args.device_map_socket()));
+    }
+    if let Some(path) = &args.ready_file {
+        dirs.extend(sandbox::parent_dir(path));
+    }
+    dirs
+}
+
+fn sandbox_paths(args: &Args) -> sandbox::SandboxPaths {
+    let mut writable = vec![PathBuf::from(&args.kubelet_dir)];
+    writable.extend(owned_dirs(args));
+    if let Some(path) = &args.nfd_features_file {
+        writable.extend(sandbox::parent_dir(path));
+    }
+
+    let mut readable = args.cdi_spec_dirs.clone();
+    readable.extend(sandbox::parent_dir(&args.pod_resources_socket));
+
+    // nvidia-smi talks to the driver through /dev/nvidiactl and the GPU nodes.
+    let mut devices: Vec<PathBuf> = glob("/dev/nvidia*")
+        .map(|paths| paths.flatten().collect())
+        .unwrap_or_default();
+    let mut executable = vec![PathBuf::from("nvidia-smi")];
+    executable.extend(args.retirement_hook.iter().cloned());
+    // The IMEX health check runs nvidia-imex-ctl, which also opens the channels.
+    if args.imex_channels != imex::Mode::Off {
+        readable.push(PathBuf::from(imex::IMEX_CONFIG_DIR));
+        devices.push(PathBuf::from(imex::IMEX_CHANNEL_DIR));
+        executable.push(PathBuf::from("nvidia-imex-ctl"));
+    }
+
+    sandbox::SandboxPaths {
+        writable,
+        readable,
+        devices,
+        executable,
+    }
+}
+
+fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
     if !args.resource_name.contains('/') {
         anyhow::bail!("resource-name must be fully qualified, e.g. nvidia.com/gpu");
     }
 
-    let socket_path = Path::new(&args.kubelet_dir).join(&args.socket_name);
+    if let Some(command) = &args.command {
+        return tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?
+            .block_on(run_command(command, &args));
+    }
+
+    validate(&args)?;
+
+    socket::adopt(systemd::listen_fds());
+
+    // Set before the runtime starts its worker threads so that every thread inherits it; root
+    // itself is only given up once sockets are bound (see run).
+    if args.run_as_uid.is_some() {
+        privileges::set_no_new_privs()?;
+    }
+
+    if args.uses_cdi() {
+        let check = preflight::runtime_check(&args.containerd_config, &args.crio_config_dir);
+        preflight::enforce(args.cdi_preflight, &check)?;
+    }
+
+    // Discovery finishes before the filesystem sandbox is applied so that its rules can name the
+    // device nodes, even when the driver loads late. Nothing else runs yet, so one thread will do.
+    let budget = startup::RetryBudget::new(Duration::from_secs(args.startup_retry_secs));
+    let discovered = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(discover_with_retry(&args, &budget))?;
 
+    if !args.no_sandbox {
+        let paths = sandbox_paths(&args);
+        // Landlock only grants access beneath paths that exist when the ruleset is created.
+        for dir in &paths.writable {
+            std::fs::create_dir_all(dir)?;
+        }
+        sandbox::restrict_filesystem(&paths)?;
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(args, budget, discovered))
+}
+
+// Checks flag combinations that are only known to be wrong once parsed.
+fn validate(args: &Args) -> anyhow::Result<()> {
+    if args.annotate_node && args.node_name.is_none() {
+        anyhow::bail!("--annotate-node requires --node-name or NODE_NAME");
+    }
+
+    if args.export_nrt && args.node_name.is_none() {
+        anyhow::bail!("--export-nrt requires --node-name or NODE_NAME");
+    }
+
+    for alias in &args.alias_resource_names {
+        if !alias.contains('/') {
+            anyhow::bail!("alias-resource-name must be fully qualified, e.g. nvidia.com/a100");
+        }
+        if *alias == args.resource_name {
+            anyhow::bail!("alias-resource-name {alias} is the same as resource-name");
+        }
+    }
+
+    if args.imex_channels != imex::Mode::Off && !args.imex_resource_name.contains('/') {
+        anyhow::bail!("imex-resource-name must be fully qualified, e.g. nvidia.com/imex-channel");
+    }
+
+    if args.imex_channels == imex::Mode::Inject && !args.uses_cdi() {
+        anyhow::bail!("--imex-channels=inject requires CDI injection (--mode cdi or --vfio-cdi)");
+    }
+
+    if args.mixed_models == models::Policy::Split && !args.alias_resource_names.is_empty() {
+        anyhow::bail!("--alias-resource-name cannot be combined with --mixed-models=split");
+    }
+
+    if args.sriov && !args.sriov_resource_name.contains('/') {
+        anyhow::bail!("sriov-resource-name must be fully qualified, e.g. nvidia.com/gpu-vf");
+    }
+
+    if (args.gpudirect || args.gpudirect_inject) && !args.gpudirect_resource_name.contains('/') {
+        anyhow::bail!("gpudirect-resource-name must be fully qualified, e.g. nvidia.com/gpudirect");
+    }
+
+    if args.gpudirect_inject && !args.uses_cdi() {
+        anyhow::bail!("--gpudirect-inject requires CDI injection (--mode cdi or --vfio-cdi)");
+    }
+
+    // The NICs would be handed out exclusively and to every GPU pod at the same time.
+    if args.gpudirect && args.gpudirect_inject {
+        anyhow::bail!("--gpudirect cannot be combined with --gpudirect-inject");
+    }
+
+    Ok(())
+}
+
+async fn discover_with_retry(
+    args: &Args,
+    budget: &startup::RetryBudget,
+) -> anyhow::Result<(BTreeMap<String, k8s::Device>, VfioDevices)> {
+    budget
+        .retry("device discovery", || async {
+            if args.mode == Mode::Cdi && !gpu::driver_loaded() {
+                return Err(
+                    startup::Transient("NVIDIA driver is not loaded yet".to_string()).into(),
+                );
+            }
+            // Nodes can only be created once the driver is loaded, which may be after we start.
+            if args.create_device_nodes {
+                devnodes::create_missing();
+            }
+            // udev may still be creating the device nodes of a freshly loaded driver.
+            let nvidiactl = Path::new("/dev/nvidiactl");
+            if args.mode == Mode::Cdi
+                && let Err(err) = std::fs::metadata(nvidiactl)
+            {
+                return Err(startup::missing(nvidiactl, err));
+            }
+            // Only now, so that a driver loading late still gets persistence mode.
+            if args.persistence_mode
+                && let Err(err) = persistence::enable()
+            {
+                eprintln!("warning: could not enable persistence mode: {err}");
+            }
+            discover_resource_devices(args)
+        })
+        .await
+}
+
+async fn run(
+    args: Args,
+    budget: startup::RetryBudget,
+    (devices, vfio_devices): (BTreeMap<String, k8s::Device>, VfioDevices),
+) -> anyhow::Result<()> {
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
-    let plugin = NvidiaCdiDevicePlugin::new(args.resource_name.clone(), shutdown_rx.clone())?;
-    let device_count = plugin.devices.len();
-    let plugin_for_server = plugin.clone();
-    let server = start_device_plugin_server(plugin_for_server, socket_path.clone()).await?;
-    let server_handle = Arc::new(Mutex::new(server));
+    let device_count = devices.len();
+    let gpu_numa_nodes: BTreeSet<Option<i64>> = devices.values().map(numa_node).collect();
 
-    wait_for_socket(&socket_path, Duration::from_secs(5)).await?;
-    register_with_kubelet(&args.kubelet_dir, &args.socket_name, &args.resource_name).await?;
-    let reg_task = maintain_registration(
-        args.kubelet_dir.clone(),
-        args.socket_name.clone(),
-        args.resource_name.clone(),
-        plugin,
-        socket_path.clone(),
-        server_handle.clone(),
-        shutdown_rx,
-    )
-    .await;
+    let models = discover_device_models(&args, &vfio_devices)?;
+    let split = models::enforce(args.mixed_models, &args.resource_name, &devices, &models)?;
+
+    let imex_channels = match args.imex_channels {
+        imex::Mode::Off => Vec::new(),
+        _ => imex::discover_channels(),
+    };
+    if args.imex_channels != imex::Mode::Off && imex_channels.is_empty() {
+        eprintln!("warning: no IMEX channels found");
+    }
+    let injected_cdi_devices = match args.imex_channels {
+        imex::Mode::Inject => {
+            println!(
+                "injecting {} IMEX channels with every GPU allocation",
+                imex_channels.len()
+            );
+            imex::cdi_names(&args.imex_resource_name, &imex_channels)
+        }
+        _ => Vec::new(),
+    };
+    let rdma_devices = (args.gpudirect || args.gpudirect_inject)
+        .then(|| rdma::gpudirect_devices(&args.gpudirect_resource_name, &gpu_numa_nodes))
+        .unwrap_or_default();
+    let paired_cdi_devices = if args.gpudirect_inject {
+        println!(
+            "injecting {} GPUDirect RDMA devices with GPU allocations on the same NUMA node",
+            rdma_devices.len()
+        );
+        rdma::by_numa_node(&rdma_devices)
+    } else {
+        BTreeMap::new()
+    };
+    let gpu_plugin = |resource_name: &str, devs| {
+        NvidiaCdiDevicePlugin::new(resource_name.to_string(), devs, shutdown_rx.clone())
+            .with_vfio_devices(vfio_devices.clone(), args.vfio_cdi)
+            .with_extra_cdi_devices(injected_cdi_devices.clone())
+            .with_numa_cdi_devices(paired_cdi_devices.clone())
+    };
+
+    // Aliases and per-model resources keep the original device IDs, so the CDI names they
+    // resolve to stay valid.
+    let mut plugins = Vec::new();
+    match split {
+        Some(split) => {
+            for (resource_name, devs) in split {
+                println!("advertising {} devices as {resource_name}", devs.len());
+                let socket_name = resource_socket_name(&resource_name);
+                plugins.push((gpu_plugin(&resource_name, devs), socket_name));
+            }
+        }
+        None => plugins.push((
+            gpu_plugin(&args.resource_name, devices.clone()),
+            args.socket_name.clone(),
+        )),
+    }
+    for alias in &args.alias_resource_names {
+        println!("also advertising {device_count} devices as {alias}");
+        plugins.push((
+            gpu_plugin(alias, devices.clone()),
+            resource_socket_name(alias),
+        ));
+    }
+
+    let occupancy = (!args.alias_resource_names.is_empty()).then(|| {
+        let occupancy = occupancy::Occupancy::new(
+            plugins
+                .iter()
+                .map(|(plugin, _)| (plugin.resource_name.clone(), plugin.devices.clone()))
+                .collect(),
+        );
+        plugins = std::mem::take(&mut plugins)
+            .into_iter()
+            .map(|(plugin, socket_name)| (plugin.with_occupancy(occupancy.clone()), socket_name))
+            .collect();
+        occupancy
+    });
+
+    // Every plugin so far advertises GPUs, under the original device IDs.
+    let gpu_stores: Vec<SharedDevices> = plugins
+        .iter()
+        .map(|(plugin, _)| plugin.devices.clone())
+        .collect();
+
+    if args.gpudirect {
+        println!(
+            "advertising {} GPUDirect RDMA devices as {}",
+            rdma_devices.len(),
+            args.gpudirect_resource_name
+        );
+        let plugin = NvidiaCdiDevicePlugin::new(
+            args.gpudirect_resource_name.clone(),
+            rdma_devices,
+            shutdown_rx.clone(),
+        );
+        plugins.push((plugin, args.gpudirect_socket_name.clone()));
+    }
+
+    if args.sriov {
+        let (vf_devices, vfio_devices) = sriov::discover_vfs(&args.sriov_resource_name);
+        println!(
+            "advertising {} SR-IOV virtual functions as {}",
+            vf_devices.len(),
+            args.sriov_resource_name
+        );
+        let plugin = NvidiaCdiDevicePlugin::new(
+            args.sriov_resource_name.clone(),
+            vf_devices,
+            shutdown_rx.clone(),
+        )
+        .with_vfio_devices(vfio_devices, args.vfio_cdi);
+        plugins.push((plugin, args.sriov_socket_name.clone()));
+    }
+
+    let imex_devices = (args.imex_channels == imex::Mode::Resource).then(|| {
+        println!(
+            "advertising {} IMEX channels as {}",
+            imex_channels.len(),
+            args.imex_resource_name
+        );
+        let plugin = NvidiaCdiDevicePlugin::new(
+            args.imex_resource_name.clone(),
+            imex::channel_devices(&args.imex_resource_name, &imex_channels),
+            shutdown_rx.clone(),
+        );
+        let devices = plugin.devices.clone();
+        plugins.push((plugin, args.imex_socket_name.clone()));
+        devices
+    });
+
+    let advertised: Vec<(String, SharedDevices)> = plugins
+        .iter()
+        .map(|(plugin, _)| (plugin.resource_name.clone(), plugin.devices.clone()))
+        .collect();
+
+    // Apply persisted retirements before kubelet sees the first device list. VFIO device IDs are
+    // PCI bus IDs already; only positional GPU device IDs need their UUID to be found again.
+    let gpu_uuids = match args.mode {
+        Mode::Cdi => discover_device_gpus(&args.resource_name)?
+            .into_iter()
+            .map(|(id, gpu)| (id, gpu.uuid))
+            .collect(),
+        Mode::Vfio => BTreeMap::new(),
+    };
+    let retirements = retirement::Retirements::load(
+        args.retirement_state_file(),
+        advertised.iter().cloned().collect(),
+        gpu_uuids,
+        args.retirement_hook.clone(),
+    )?;
+    let (supervisor, mut failed) = supervisor::Supervisor::new(shutdown_rx.clone());
+    let peers = peercred::PeerPolicy::new(
+        args.allowed_peer_uids.clone(),
+        args.allowed_peer_gids.clone(),
+    );
+    let limits = rpc::RpcLimits {
+        max_concurrent: args.max_concurrent_rpcs,
+        timeout: Duration::from_secs(args.rpc_timeout_secs),
+    };
+
+    let retirement_watcher = {
+        let retirements = retirements.clone();
+        let socket = args.pod_resources_socket.clone();
+        let shutdown = shutdown_rx.clone();
+        supervisor.spawn("retirement watcher", move || {
+            let handle = retirements
+                .clone()
+                .spawn_watcher(socket.clone(), shutdown.clone());
+            future::ready(Ok(handle))
+        })
+    };
+
+    let occupancy_reconciler = occupancy.map(|occupancy| {
+        let socket = args.pod_resources_socket.clone();
+        let shutdown = shutdown_rx.clone();
+        supervisor.spawn("alias occupancy tracker", move || {
+            let handle = occupancy
+                .clone()
+                .spawn_reconciler(socket.clone(), shutdown.clone());
+            future::ready(Ok(handle))
+        })
+    });
+
+    // GPUs behind an NVSwitch are unusable until the fabric manager has set up the fabric. Only
+    // GPUs the driver manages can be checked.
+    let fabric_gpus = match args.mode {
+        Mode::Cdi if args.fabric_check_interval_secs > 0 => {
+            discover_device_gpus(&args.resource_name)?
+        }
+        _ => BTreeMap::new(),
+    };
+    let fabric_monitor = (!fabric_gpus.is_empty()).then(|| {
+        let stores = gpu_stores.clone();
+        let bus_ids: BTreeMap<String, String> = fabric_gpus
+            .iter()
+            .map(|(id, gpu)| (id.clone(), gpu.pci_bus_id.clone()))
+            .collect();
+        let period = Duration::from_secs(args.fabric_check_interval_secs);
+        let shutdown = shutdown_rx.clone();
+        supervisor.spawn("NVLink fabric monitor", move || {
+            future::ready(Ok(fabric::spawn_fabric_monitor(
+                stores.clone(),
+                bus_ids.clone(),
+                period,
+                shutdown.clone(),
+            )))
+        })
+    });
+
+    let imex_health_checker = (args.imex_channels != imex::Mode::Off).then(|| {
+        // Injected channels are useless while the daemon is down, so the GPUs are withheld.
+        let stores = match imex_devices {
+            Some(channels) => vec![channels],
+            None => gpu_stores.clone(),
+        };
+        let shutdown = shutdown_rx.clone();
+        supervisor.spawn("IMEX health checker", move || {
+            future::ready(Ok(imex::spawn_health_checker(
+                stores.clone(),
+                shutdown.clone(),
+            )))
+        })
+    });
+
+    // During an upgrade the previous instance keeps serving until we have registered on
+    // alternate sockets; kubelet then switches over without the devices ever disappearing.
+    let upgrading = args.upgrade && socket::is_live(&args.admin_socket());
+    if upgrading {
+        println!("another instance is running, taking over once registered");
+    }
+
+    let lost_registration = Arc::new(Notify::new());
+    let mut plugin_tasks = Vec::with_capacity(plugins.len());
+    let mut plugin_sockets = Vec::with_capacity(plugins.len());
+    for (plugin, socket_name) in plugins {
+        let socket_name = if upgrading {
+            socket::upgrade_socket_name(Path::new(&args.kubelet_dir), &socket_name)
+        } else {
+            socket_name
+        };
+        plugin_sockets.push(Path::new(&args.kubelet_dir).join(&socket_name));
+        let plugin = plugin
+            .with_peer_policy(peers.clone())
+            .with_rpc_limits(limits);
+        let what = format!("registering {}", plugin.resource_name);
+        plugin_tasks.push(
+            budget
+                .retry(&what, || {
+                    serve_plugin(
+                        plugin.clone(),
+                        &args.kubelet_dir,
+                        &socket_name,
+                        args.force,
+                        &supervisor,
+                        lost_registration.clone(),
+                        shutdown_rx.clone(),
+                    )
+                })
+                .await?,
+        );
+    }
+
+    if upgrading {
+        admin::hand_off(args.admin_socket(), &args.handoff_token_file()).await?;
+    }
+
+    // Fail fast if the admin socket cannot be claimed; the supervisor only covers later crashes.
+    let handoff = Arc::new(Notify::new());
+    let handoff_token = admin::new_handoff_token()?;
+    let admin_server = {
+        let socket = args.admin_socket();
+        let peers = peers.clone();
+        let handoff = handoff.clone();
+        let token = handoff_token.clone();
+        let force = args.force;
+        let mut first = Some(admin::start_admin_server(
+            socket.clone(),
+            admin::AdminService::new(retirements.clone(), handoff.clone(), token.clone()),
+            peers.clone(),
+            limits,
+            force,
+        )?);
+        supervisor.spawn("admin server", move || {
+            let handle = first.take().map(Ok).unwrap_or_else(|| {
+                admin::start_admin_server(
+                    socket.clone(),
+                    admin::AdminService::new(retirements.clone(), handoff.clone(), token.clone()),
+                    peers.clone(),
+                    limits,
+                    false,
+                )
+            });
+            future::ready(handle)
+        })
+    };
+    // Only written once we own the admin socket, so a failed start leaves the running instance's
+    // token in place.
+    admin::write_handoff_token(&args.handoff_token_file(), &handoff_token)?;
+
+    let device_map_server = if args.device_map {
+        let socket = args.device_map_socket();
+        let gpus = match args.mode {
+            Mode::Cdi => discover_device_gpus(&args.resource_name)?,
+            // The driver does not see GPUs bound to vfio-pci; only their PCI location is known.
+            Mode::Vfio => vfio_devices
+                .iter()
+                .map(|(id, dev)| {
+                    let gpu = gpu::GpuInfo {
+                        pci_bus_id: dev.pci_bus_id.clone(),
+                        numa_node: dev.numa_node,
+                        ..Default::default()
+                    };
+                    (id.clone(), gpu)
+                })
+                .collect(),
+        };
+        let details = devicemap::details();
+        let refresher = {
+            let details = details.clone();
+            let socket = args.pod_resources_socket.clone();
+            let shutdown = shutdown_rx.clone();
+            supervisor.spawn("device map refresher", move || {
+                let handle =
+                    devicemap::spawn_refresher(details.clone(), socket.clone(), shutdown.clone());
+                future::ready(Ok(handle))
+            })
+        };
+        let resources = advertised.clone();
+        // The device map is for monitoring agents, which need not be allowed to talk to the
+        // plugin or admin sockets.
+        let peers = peercred::PeerPolicy::new(
+            args.device_map_allowed_uids.clone(),
+            args.device_map_allowed_gids.clone(),
+        );
+        let service = move || {
+            devicemap::DeviceMapService::new(resources.clone(), gpus.clone(), details.clone())
+        };
+        let mut first = Some(devicemap::start_device_map_server(
+            socket.clone(),
+            service(),
+            peers.clone(),
+            limits,
+            args.force,
+        )?);
+        let server = supervisor.spawn("device map server", move || {
+            let handle = first.take().map(Ok).unwrap_or_else(|| {
+                devicemap::start_device_map_server(
+                    socket.clone(),
+                    service(),
+                    peers.clone(),
+                    limits,
+                    false,
+                )
+            });
+            future::ready(handle)
+        });
+        Some((server, refresher))
+    } else {
+        None
+    };
+
+    // Every socket is bound and registered, so root is no longer needed. Only our own directories
+    // are handed to the group; kubelet's sockets and directories stay root-only, so PodResources
+    // is connected to beforehand. kubelet restarting wipes our socket, and we start over as root
+    // (see maintain_registration).
+    if let Some(uid) = args.run_as_uid {
+        let gid = args.run_as_gid.unwrap_or(uid);
+        for dir in owned_dirs(&args) {
+            std::fs::create_dir_all(&dir)?;
+            privileges::share_directory(&dir, gid)?;
+        }
+        if let Err(err) = podresources::connect(&args.pod_resources_socket).await {
+            eprintln!("warning: failed to connect to the PodResources API: {err}");
+        }
+        privileges::drop_privileges(uid, gid)?;
+    }
+
+    if !args.no_sandbox {
+        sandbox::restrict_syscalls()?;
+    }
+
+    let annotator = match (&args.node_name, args.annotate_node) {
+        (Some(node_name), true) => {
+            let node_name = node_name.clone();
+            let resource_name = args.resource_name.clone();
+            let cdi_dirs = args.cdi_spec_dirs.clone();
+            let interval = Duration::from_secs(args.node_info_interval_secs);
+            let shutdown = shutdown_rx.clone();
+            Some(supervisor.spawn("node annotator", move || {
+                node_annotations::spawn_node_annotator(
+                    node_name.clone(),
+                    resource_name.clone(),
+                    cdi_dirs.clone(),
+                    interval,
+                    shutdown.clone(),
+                )
+            }))
+        }
+        _ => None,
+    };
+
+    let nrt_exporter = match (&args.node_name, args.export_nrt) {
+        (Some(node_name), true) => {
+            let node_name = node_name.clone();
+            let socket = args.pod_resources_socket.clone();
+            let aliases: BTreeSet<String> = args.alias_resource_names.iter().cloned().collect();
+            let interval = Duration::from_secs(args.nrt_interval_secs);
+            let shutdown = shutdown_rx.clone();
+            Some(supervisor.spawn("NodeResourceTopology exporter", move || {
+                nrt::spawn_nrt_exporter(
+                    node_name.clone(),
+                    socket.clone(),
+                    advertised.clone(),
+                    aliases.clone(),
+                    interval,
+                    shutdown.clone(),
+                )
+            }))
+        }
+        _ => None,
+    };
+
+    let nfd_writer = args.nfd_features_file.clone().map(|path| {
+        let resource_name = args.resource_name.clone();
+        let cdi_dirs = args.cdi_spec_dirs.clone();
+        let interval = Duration::from_secs(args.node_info_interval_secs);
+        let shutdown = shutdown_rx.clone();
+        supervisor.spawn("NFD feature writer", move || {
+            future::ready(Ok(nfd::spawn_nfd_writer(
+                path.clone(),
+                resource_name.clone(),
+                cdi_dirs.clone(),
+                interval,
+                shutdown.clone(),
+            )))
+        })
+    });
 
     println!(
         "nvidia CDI device plugin running. resource={} devices={}",
         args.resource_name, device_count
     );
+    // Every plugin has registered with kubelet by now.
+    systemd::notify(&format!(
+        "READY=1\nSTATUS=advertising {device_count} devices as {}",
+        args.resource_name
+    ));
+    let watchdog = systemd::spawn_watchdog(shutdown_rx.clone());
+    if let Some(path) = &args.ready_file {
+        std::fs::write(path, format!("{}\n", std::process::id()))?;
+    }
 
-    tokio::signal::ctrl_c().await?;
-    println!("shutdown requested, stopping server");
+    let mut handed_off = false;
+    let result = select! {
+        signal = shutdown_signal() => {
+            println!("shutdown requested, stopping server");
+            signal
+        }
+        _ = handoff.notified() => {
+            handed_off = true;
+            // Let the admin server deliver its reply before it is torn down.
+            sleep(Duration::from_millis(500)).await;
+            Ok(())
+        }
+        Some(name) = failed.recv() => Err(anyhow::anyhow!("{name} failed repeatedly, shutting down")),
+        _ = lost_registration.notified() => {
+            Err(anyhow::anyhow!("cannot re-register with kubelet without root, exiting to be restarted"))
+        }
+    };
+    systemd::notify("STOPPING=1");
+    if let Some(path) = &args.ready_file {
+        let _ = std::fs::remove_file(path);
+    }
+    // After a handoff the new instance owns the NFD feature file, so don't remove it.
+    let nfd_writer = match nfd_writer {
+        Some(nfd_writer) if handed_off => {
+            nfd_writer.abort();
+            None
+        }
+        nfd_writer => nfd_writer,
+    };
     let _ = shutdown_tx.send(true);
+    if let Some(watchdog) = watchdog {
+        watchdog.abort();
+    }
+    for tasks in &plugin_tasks {
+        tasks.abort().await;
+    }
+    // The next instance goes back to the primary socket names, so don't leave upgrade ones behind.
+    for path in plugin_sockets
+        .iter()
+        .filter(|path| socket::is_upgrade_socket(path))
     {
-        let handle = server_handle.lock().await;
-        handle.abort();
+        let _ = std::fs::remove_file(path);
+    }
+    retirement_watcher.abort();
+    if let Some(occupancy_reconciler) = occupancy_reconciler {
+        occupancy_reconciler.abort();
+    }
+    if let Some(fabric_monitor) = fabric_monitor {
+        fabric_monitor.abort();
+    }
+    if let Some(imex_health_checker) = imex_health_checker {
+        imex_health_checker.abort();
+    }
+    admin_server.abort();
+    if let Some((device_map_server, device_map_refresher)) = device_map_server {
+        device_map_server.abort();
+        device_map_refresher.abort();
+    }
+    if let Some(annotator) = annotator {
+        annotator.abort();
+    }
+    if let Some(nrt_exporter) = nrt_exporter {
+        nrt_exporter.abort();
+    }
+    // The NFD writer removes its feature file on shutdown, so let it finish.
+    if let Some(nfd_writer) = nfd_writer {
+        let _ = nfd_writer.await;
     }
-    reg_task.abort();
 
+    // Exiting would only get the pod restarted next to the new instance, which then sees this one
+    // as the instance to take over from. Wait to be deleted instead.
+    if handed_off {
+        println!("handed off, waiting to be terminated");
+        shutdown_signal().await?;
+    }
+
+    result
+}
+
+// kubelet stops pods with SIGTERM; SIGINT covers running the plugin by hand.
+async fn shutdown_signal() -> anyhow::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    select! {
+        signal = tokio::signal::ctrl_c() => signal?,
+        _ = terminate.recv() => {}
+    }
     Ok(())
 }
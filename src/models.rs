@@ -0,0 +1,108 @@
+use crate::k8s;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+
+const UNKNOWN_MODEL: &str = "unknown";
+
+/// What to do when the devices of one resource are not all the same GPU model.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// log a warning and advertise them together anyway
+    Warn,
+    /// refuse to start
+    Fail,
+    /// advertise each model as its own resource, `<resource-name>-<model>`
+    Split,
+}
+
+/// Shortens a model name into something usable in a resource name, e.g. `NVIDIA H100 80GB HBM3`
+/// becomes `h100-80gb-hbm3`.
+pub fn slug(model: &str) -> String {
+    let model = model.trim();
+    let model = model.strip_prefix("NVIDIA ").unwrap_or(model);
+    let words: Vec<String> = model
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect();
+    if words.is_empty() {
+        return UNKNOWN_MODEL.to_string();
+    }
+    words.join("-")
+}
+
+/// Groups `devices` by model, as looked up in `models` (keyed by device ID).
+pub fn group(
+    devices: &BTreeMap<String, k8s::Device>,
+    models: &BTreeMap<String, String>,
+) -> BTreeMap<String, BTreeMap<String, k8s::Device>> {
+    let mut groups: BTreeMap<String, BTreeMap<String, k8s::Device>> = BTreeMap::new();
+    for (id, dev) in devices {
+        let model = models.get(id).map_or(UNKNOWN_MODEL, String::as_str);
+        groups
+            .entry(model.to_string())
+            .or_default()
+            .insert(id.clone(), dev.clone());
+    }
+    groups
+}
+
+/// Applies `policy` to the models found under `resource_name`. Returns the resources to advertise
+/// instead when the devices are split up, or `None` to advertise them as discovered.
+pub fn enforce(
+    policy: Policy,
+    resource_name: &str,
+    devices: &BTreeMap<String, k8s::Device>,
+    models: &BTreeMap<String, String>,
+) -> anyhow::Result<Option<BTreeMap<String, BTreeMap<String, k8s::Device>>>> {
+    let groups = group(devices, models);
+    if groups.len() <= 1 {
+        return Ok(None);
+    }
+
+    let summary = groups
+        .iter()
+        .map(|(model, devs)| format!("{} x {model}", devs.len()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    match policy {
+        Policy::Warn => {
+            eprintln!("warning: {resource_name} mixes GPU models ({summary})");
+            Ok(None)
+        }
+        Policy::Fail => anyhow::bail!(
+            "{resource_name} mixes GPU models ({summary}); use --mixed-models=split to advertise them separately"
+        ),
+        Policy::Split => {
+            let mut split = BTreeMap::new();
+            for (model, devs) in groups {
+                let name = format!("{resource_name}-{}", slug(&model));
+                if split.insert(name.clone(), devs).is_some() {
+                    anyhow::bail!("GPU models under {resource_name} both map to {name}");
+                }
+            }
+            Ok(Some(split))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs_model_names() {
+        let cases = [
+            ("NVIDIA H100 80GB HBM3", "h100-80gb-hbm3"),
+            ("NVIDIA A100-SXM4-40GB", "a100-sxm4-40gb"),
+            ("  Tesla T4 ", "tesla-t4"),
+            ("NVIDIA GeForce RTX 4090", "geforce-rtx-4090"),
+            ("10de:2330", "10de-2330"),
+            ("", UNKNOWN_MODEL),
+            ("---", UNKNOWN_MODEL),
+        ];
+        for (model, want) in cases {
+            assert_eq!(slug(model), want, "{model:?}");
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{gpu::discover_gpus, persistence, versions::detect_versions};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{select, sync::watch, task::JoinHandle, time::sleep};
+
+pub const DEFAULT_NFD_FEATURES_FILE: &str =
+    "/etc/kubernetes/node-feature-discovery/features.d/nvidia-cdi-device-plugin";
+const LABEL_PREFIX: &str = "nvidia.com/";
+
+// Label values are limited to 63 alphanumerics, '-', '_' or '.', and must start and end with an
+// alphanumeric.
+fn label_value(raw: &str) -> String {
+    let value: String = raw
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .take(63)
+        .collect();
+    value
+        .trim_matches(|c: char| !c.is_ascii_alphanumeric())
+        .to_string()
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.tmp"))
+}
+
+fn features(resource_name: &str, cdi_spec_dirs: &[PathBuf]) -> Vec<(String, String)> {
+    let gpus = discover_gpus();
+    let versions = detect_versions(resource_name, cdi_spec_dirs);
+    let mut out = vec![
+        ("gpu.present".to_string(), (!gpus.is_empty()).to_string()),
+        ("gpu.count".to_string(), gpus.len().to_string()),
+    ];
+
+    if let Some(first) = gpus.first() {
+        let product = if gpus.iter().all(|gpu| gpu.model == first.model) {
+            first.model.as_str()
+        } else {
+            "mixed"
+        };
+        out.push(("gpu.product".to_string(), product.to_string()));
+    }
+    if let Some(driver) = &versions.driver {
+        out.push(("gpu.driver-version".to_string(), driver.clone()));
+        if let Some(major) = driver.split('.').next() {
+            out.push(("gpu.driver-major".to_string(), major.to_string()));
+        }
+    }
+    if let Some(cuda) = &versions.cuda {
+        out.push(("cuda.version".to_string(), cuda.clone()));
+    }
+    if let Some(cdi) = &versions.cdi_spec {
+        out.push(("cdi.spec-version".to_string(), cdi.clone()));
+    }
+    if let Some(mode) = persistence::summary(&persistence::query()) {
+        out.push(("gpu.persistence-mode".to_string(), mode.to_string()));
+    }
+
+    out.into_iter()
+        .map(|(key, value)| (format!("{LABEL_PREFIX}{key}"), label_value(&value)))
+        .collect()
+}
+
+fn write_features_file(path: &Path, features: &[(String, String)]) -> anyhow::Result<()> {
+    let mut contents = String::from("# generated by nvidia-cdi-device-plugin, do not edit\n");
+    for (key, value) in features {
+        contents.push_str(&format!("{key}={value}\n"));
+    }
+
+    // nfd-worker may read the directory at any time, so never expose a partially written file.
+    // It skips hidden files, so the temporary file is dot-prefixed.
+    let tmp = temp_path(path);
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Keeps an NFD local feature file describing the node's GPUs up to date, removing it on shutdown
+/// so stale labels do not outlive the plugin.
+pub fn spawn_nfd_writer(
+    path: PathBuf,
+    resource_name: String,
+    cdi_spec_dirs: Vec<PathBuf>,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut written: Option<Vec<(String, String)>> = None;
+        loop {
+            if *shutdown.borrow() {
+                break;
+            }
+
+            // nvidia-smi can hang for seconds on a wedged GPU, so keep it off the runtime workers.
+            let current = {
+                let resource_name = resource_name.clone();
+                let cdi_spec_dirs = cdi_spec_dirs.clone();
+                tokio::task::spawn_blocking(move || features(&resource_name, &cdi_spec_dirs)).await
+            };
+            let Ok(current) = current else {
+                break;
+            };
+            if written.as_ref() != Some(&current) {
+                match write_features_file(&path, &current) {
+                    Ok(()) => {
+                        println!("wrote NFD features file {}", path.display());
+                        written = Some(current);
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "failed to write NFD features file {}: {err}",
+                            path.display()
+                        )
+                    }
+                }
+            }
+
+            select! {
+                _ = sleep(interval) => {},
+                changed = shutdown.changed() => {
+                    if changed.is_err() || *shutdown.borrow() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if written.is_some()
+            && let Err(err) = fs::remove_file(&path)
+        {
+            eprintln!(
+                "failed to remove NFD features file {}: {err}",
+                path.display()
+            );
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitizes_label_values() {
+        let cases = [
+            ("NVIDIA H100 80GB HBM3", "NVIDIA-H100-80GB-HBM3"),
+            ("550.54.14", "550.54.14"),
+            ("(mixed)", "mixed"),
+            ("--", ""),
+            (&"a".repeat(70), &"a".repeat(63)),
+        ];
+        for (raw, want) in cases {
+            assert_eq!(label_value(raw), want, "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn hides_temporary_file() {
+        assert_eq!(
+            temp_path(Path::new(
+                "/etc/kubernetes/node-feature-discovery/features.d/nvidia-gpu"
+            )),
+            Path::new("/etc/kubernetes/node-feature-discovery/features.d/.nvidia-gpu.tmp")
+        );
+    }
+}
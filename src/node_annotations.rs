@@ -0,0 +1,89 @@
+use crate::versions::{detect_versions, VersionInfo};
+use k8s_openapi::api::core::v1::Node;
+use kube::{
+    api::{Patch, PatchParams},
+    Api, Client,
+};
+use serde_json::json;
+use std::{path::PathBuf, time::Duration};
+use tokio::{select, sync::watch, task::JoinHandle, time::sleep};
+
+const ANNOTATION_PREFIX: &str = "nvidia.com/cdi-plugin.";
+
+fn annotations(info: &VersionInfo) -> serde_json::Map<String, serde_json::Value> {
+    // Unknown versions are written as null so a merge patch removes stale values.
+    [
+        ("driver-version", info.driver.clone()),
+        ("cuda-version", info.cuda.clone()),
+        ("cdi-spec-version", info.cdi_spec.clone()),
+        ("plugin-version", Some(info.plugin.clone())),
+    ]
+    .into_iter()
+    .map(|(key, value)| (format!("{ANNOTATION_PREFIX}{key}"), json!(value)))
+    .collect()
+}
+
+async fn annotate_node(client: &Client, node_name: &str, info: &VersionInfo) -> anyhow::Result<()> {
+    let nodes: Api<Node> = Api::all(client.clone());
+    let patch = json!({ "metadata": { "annotations": annotations(info) } });
+    nodes
+        .patch(node_name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await?;
+    Ok(())
+}
+
+/// Publishes detected driver/CUDA/CDI/plugin versions on the node object, re-patching only when
+/// something changed since the last successful update.
+pub async fn spawn_node_annotator(
+    node_name: String,
+    resource_name: String,
+    cdi_spec_dirs: Vec<PathBuf>,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<JoinHandle<()>> {
+    let client = Client::try_default().await?;
+
+    Ok(tokio::spawn(async move {
+        let mut published: Option<VersionInfo> = None;
+        loop {
+            if *shutdown.borrow() {
+                break;
+            }
+
+            // nvidia-smi can hang for seconds on a wedged GPU, so keep it off the runtime workers.
+            let info = {
+                let resource_name = resource_name.clone();
+                let cdi_spec_dirs = cdi_spec_dirs.clone();
+                tokio::task::spawn_blocking(move || detect_versions(&resource_name, &cdi_spec_dirs))
+                    .await
+            };
+            let Ok(info) = info else {
+                break;
+            };
+            if published.as_ref() != Some(&info) {
+                match annotate_node(&client, &node_name, &info).await {
+                    Ok(()) => {
+                        println!(
+                            "annotated node {node_name}: driver={} cuda={} cdi={} plugin={}",
+                            info.driver.as_deref().unwrap_or("unknown"),
+                            info.cuda.as_deref().unwrap_or("unknown"),
+                            info.cdi_spec.as_deref().unwrap_or("unknown"),
+                            info.plugin
+                        );
+                        published = Some(info);
+                    }
+                    Err(err) => eprintln!("failed to annotate node {node_name}: {err}"),
+                }
+            }
+
+            select! {
+                _ = sleep(interval) => {},
+                changed = shutdown.changed() => {
+                    if changed.is_err() || *shutdown.borrow() {
+                        break;
+                    }
+                }
+            }
+        }
+    }))
+}
@@ -0,0 +1,325 @@
+use crate::{
+    numa_node, podresources,
+    store::{HealthReason, Snapshot},
+    SharedDevices,
+};
+use kube::{
+    api::{ApiResource, DynamicObject, PostParams},
+    Api, Client,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{select, sync::watch, task::JoinHandle, time::sleep};
+
+fn nrt_resource() -> ApiResource {
+    ApiResource {
+        group: "topology.node.k8s.io".to_string(),
+        version: "v1alpha2".to_string(),
+        api_version: "topology.node.k8s.io/v1alpha2".to_string(),
+        kind: "NodeResourceTopology".to_string(),
+        plural: "noderesourcetopologies".to_string(),
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct ZoneCounts {
+    capacity: usize,
+    allocatable: usize,
+    available: usize,
+}
+
+/// Per-NUMA-zone device counts, keyed by zone name and then resource name.
+type Zones = BTreeMap<String, BTreeMap<String, ZoneCounts>>;
+
+fn zone_counts(
+    resources: &[(String, Arc<Snapshot>)],
+    allocated: &BTreeMap<String, BTreeSet<String>>,
+) -> Zones {
+    let mut zones = Zones::new();
+    for (resource_name, devices) in resources {
+        let in_use = allocated.get(resource_name);
+        for dev in devices.devices().values() {
+            // Devices without NUMA affinity cannot be placed in a zone.
+            let Some(node) = numa_node(dev) else {
+                continue;
+            };
+            let counts = zones
+                .entry(format!("node-{node}"))
+                .or_default()
+                .entry(resource_name.clone())
+                .or_default();
+            counts.capacity += 1;
+            // A device allocated through an alias is in use, not broken.
+            let in_use_elsewhere = devices.has_reason(&dev.id, HealthReason::InUseElsewhere);
+            if devices
+                .reasons(&dev.id)
+                .all(|reason| reason == HealthReason::InUseElsewhere)
+            {
+                counts.allocatable += 1;
+                if !in_use_elsewhere && !in_use.is_some_and(|ids| ids.contains(&dev.id)) {
+                    counts.available += 1;
+                }
+            }
+        }
+    }
+    zones
+}
+
+/// Replaces our resources in the `zones` of an existing NRT object, keeping entries owned by
+/// other exporters (CPU, memory, other devices) intact.
+fn merge_zones(existing: &Value, ours: &Zones, owned: &BTreeSet<String>) -> Value {
+    let mut zones: Vec<Value> = existing
+        .get("zones")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+
+    for zone in &mut zones {
+        if let Some(resources) = zone.get_mut("resources").and_then(Value::as_array_mut) {
+            resources.retain(|res| {
+                !res.get("name")
+                    .and_then(Value::as_str)
+                    .is_some_and(|name| owned.contains(name))
+            });
+        }
+    }
+
+    for (zone_name, resources) in ours {
+        let entries = resources.iter().map(|(name, counts)| {
+            json!({
+                "name": name,
+                "capacity": counts.capacity.to_string(),
+                "allocatable": counts.allocatable.to_string(),
+                "available": counts.available.to_string(),
+            })
+        });
+        let existing_zone = zones
+            .iter_mut()
+            .find(|zone| zone.get("name").and_then(Value::as_str) == Some(zone_name.as_str()));
+        match existing_zone {
+            Some(zone) => {
+                if zone.get("resources").and_then(Value::as_array).is_none() {
+                    zone["resources"] = json!([]);
+                }
+                if let Some(list) = zone["resources"].as_array_mut() {
+                    list.extend(entries);
+                }
+            }
+            None => zones.push(json!({
+                "name": zone_name,
+                "type": "Node",
+                "resources": entries.collect::<Vec<_>>(),
+            })),
+        }
+    }
+
+    Value::Array(zones)
+}
+
+async fn publish(
+    api: &Api<DynamicObject>,
+    node_name: &str,
+    zones: &Zones,
+    owned: &BTreeSet<String>,
+) -> anyhow::Result<()> {
+    match api.get_opt(node_name).await? {
+        Some(mut obj) => {
+            obj.data["zones"] = merge_zones(&obj.data, zones, owned);
+            api.replace(node_name, &PostParams::default(), &obj).await?;
+        }
+        None => {
+            let obj = DynamicObject::new(node_name, &nrt_resource()).data(json!({
+                "zones": merge_zones(&Value::Null, zones, owned),
+            }));
+            api.create(&PostParams::default(), &obj).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Keeps the node's NodeResourceTopology object in sync with per-NUMA-zone device capacity and
+/// the allocations kubelet reports through the PodResources API.
+///
+/// Resources named in `aliases` advertise the same devices as another resource, so they are not
+/// exported as capacity of their own; only entries they left behind are removed.
+pub async fn spawn_nrt_exporter(
+    node_name: String,
+    pod_resources_socket: PathBuf,
+    resources: Vec<(String, SharedDevices)>,
+    aliases: BTreeSet<String>,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<JoinHandle<()>> {
+    let client = Client::try_default().await?;
+    let api: Api<DynamicObject> = Api::all_with(client, &nrt_resource());
+    let owned: BTreeSet<String> = resources.iter().map(|(name, _)| name.clone()).collect();
+    let resources: Vec<(String, SharedDevices)> = resources
+        .into_iter()
+        .filter(|(name, _)| !aliases.contains(name))
+        .collect();
+
+    Ok(tokio::spawn(async move {
+        let mut published: Option<Zones> = None;
+        loop {
+            if *shutdown.borrow() {
+                break;
+            }
+
+            match podresources::list_assignments(&pod_resources_socket).await {
+                Ok(assignments) => {
+                    let allocated = podresources::allocated_ids(&assignments);
+                    let snapshot: Vec<(String, Arc<Snapshot>)> = resources
+                        .iter()
+                        .map(|(name, devices)| (name.clone(), devices.snapshot()))
+                        .collect();
+                    let zones = zone_counts(&snapshot, &allocated);
+                    if published.as_ref() != Some(&zones) {
+                        match publish(&api, &node_name, &zones, &owned).await {
+                            Ok(()) => published = Some(zones),
+                            Err(err) => {
+                                eprintln!(
+                                    "failed to update NodeResourceTopology {node_name}: {err}"
+                                )
+                            }
+                        }
+                    }
+                }
+                Err(err) => eprintln!("failed to list pod resources: {err}"),
+            }
+
+            select! {
+                _ = sleep(interval) => {},
+                changed = shutdown.changed() => {
+                    if changed.is_err() || *shutdown.borrow() {
+                        break;
+                    }
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{k8s, store::DeviceStore, topology_info};
+
+    fn counts(capacity: usize, allocatable: usize, available: usize) -> ZoneCounts {
+        ZoneCounts {
+            capacity,
+            allocatable,
+            available,
+        }
+    }
+
+    // Devices as (id, NUMA node).
+    fn store(devices: &[(&str, Option<i64>)]) -> DeviceStore {
+        DeviceStore::new(
+            devices
+                .iter()
+                .map(|(id, node)| {
+                    let dev = k8s::Device {
+                        id: id.to_string(),
+                        health: "Healthy".to_string(),
+                        topology: topology_info(*node),
+                    };
+                    (id.to_string(), dev)
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn counts_devices_per_zone() {
+        let gpus = store(&[
+            ("gpu=0", Some(0)),
+            ("gpu=1", Some(0)),
+            ("gpu=2", Some(1)),
+            ("gpu=3", Some(1)),
+            ("gpu=4", None),
+        ]);
+        gpus.set_unhealthy("gpu=1", HealthReason::Fabric, true);
+        gpus.set_unhealthy("gpu=2", HealthReason::InUseElsewhere, true);
+        let allocated = BTreeMap::from([(
+            "nvidia.com/gpu".to_string(),
+            BTreeSet::from(["gpu=3".to_string()]),
+        )]);
+
+        let zones = zone_counts(
+            &[("nvidia.com/gpu".to_string(), gpus.snapshot())],
+            &allocated,
+        );
+        let got: Vec<(&str, &ZoneCounts)> = zones
+            .iter()
+            .map(|(zone, resources)| (zone.as_str(), &resources["nvidia.com/gpu"]))
+            .collect();
+        assert_eq!(
+            got,
+            [("node-0", &counts(2, 1, 1)), ("node-1", &counts(2, 2, 0))]
+        );
+    }
+
+    #[test]
+    fn merges_zones_with_other_exporters() {
+        let existing = json!({
+            "zones": [
+                {
+                    "name": "node-0",
+                    "type": "Node",
+                    "resources": [
+                        {"name": "cpu", "capacity": "32"},
+                        {"name": "nvidia.com/gpu", "capacity": "4"},
+                        {"name": "nvidia.com/a100", "capacity": "4"}
+                    ]
+                },
+                {"name": "node-1", "type": "Node"}
+            ]
+        });
+        let ours = Zones::from([
+            (
+                "node-0".to_string(),
+                BTreeMap::from([("nvidia.com/gpu".to_string(), counts(2, 2, 1))]),
+            ),
+            (
+                "node-1".to_string(),
+                BTreeMap::from([("nvidia.com/gpu".to_string(), counts(1, 1, 1))]),
+            ),
+            (
+                "node-2".to_string(),
+                BTreeMap::from([("nvidia.com/gpu".to_string(), counts(1, 0, 0))]),
+            ),
+        ]);
+        let owned = BTreeSet::from(["nvidia.com/gpu".to_string(), "nvidia.com/a100".to_string()]);
+
+        let cases = [
+            (existing, vec!["cpu=32", "nvidia.com/gpu=2"]),
+            (Value::Null, vec!["nvidia.com/gpu=2"]),
+        ];
+        for (existing, node0) in cases {
+            let merged = merge_zones(&existing, &ours, &owned);
+            let zones = merged.as_array().unwrap();
+            let names: Vec<&str> = zones.iter().map(|z| z["name"].as_str().unwrap()).collect();
+            assert_eq!(names, ["node-0", "node-1", "node-2"], "{existing}");
+            let resources: Vec<String> = zones[0]["resources"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|res| {
+                    format!(
+                        "{}={}",
+                        res["name"].as_str().unwrap(),
+                        res["capacity"].as_str().unwrap()
+                    )
+                })
+                .collect();
+            assert_eq!(resources, node0, "{existing}");
+            assert_eq!(zones[1]["resources"][0]["available"], "1", "{existing}");
+            assert_eq!(zones[2]["type"], "Node", "{existing}");
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{podresources, store::HealthReason, SharedDevices};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{select, sync::watch, task::JoinHandle, time::sleep};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How long a device claimed by Allocate stays held before PodResources has to confirm it; the
+/// assignment only shows up there once the pod has been admitted.
+const CLAIM_GRACE: Duration = Duration::from_secs(120);
+
+struct Hold {
+    resource_name: String,
+    last_seen: Instant,
+}
+
+/// Shared occupancy for resources that advertise the same physical devices under different names
+/// (e.g. `nvidia.com/gpu` and a per-model alias during a migration). While a device is allocated
+/// through one name it is reported unhealthy under all the others, so kubelet never hands it out
+/// twice.
+pub struct Occupancy {
+    resources: BTreeMap<String, SharedDevices>,
+    holds: Mutex<BTreeMap<String, Hold>>,
+}
+
+impl Occupancy {
+    pub fn new(resources: BTreeMap<String, SharedDevices>) -> Arc<Self> {
+        Arc::new(Self {
+            resources,
+            holds: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    // Marks `device_id` as in use under every resource except `holder`, or clears that again.
+    fn mask(&self, holder: &str, device_id: &str, in_use: bool) {
+        for (resource_name, devices) in &self.resources {
+            if resource_name != holder {
+                devices.set_unhealthy(device_id, HealthReason::InUseElsewhere, in_use);
+            }
+        }
+    }
+
+    fn hold(&self, holds: &mut BTreeMap<String, Hold>, resource_name: &str, device_id: &str) {
+        if let Some(hold) = holds.get_mut(device_id) {
+            hold.last_seen = Instant::now();
+            return;
+        }
+        self.mask(resource_name, device_id, true);
+        holds.insert(
+            device_id.to_string(),
+            Hold {
+                resource_name: resource_name.to_string(),
+                last_seen: Instant::now(),
+            },
+        );
+    }
+
+    /// Records that kubelet allocated `device_ids` through `resource_name`. Fails without holding
+    /// any of them if one is already in use through another name, which can happen if kubelet had
+    /// not yet seen it become unavailable there.
+    pub fn claim(&self, resource_name: &str, device_ids: &[String]) -> anyhow::Result<()> {
+        let mut holds = self.holds.lock().unwrap();
+        for id in device_ids {
+            if let Some(hold) = holds.get(id)
+                && hold.resource_name != resource_name
+            {
+                anyhow::bail!("device {id} is already allocated as {}", hold.resource_name);
+            }
+        }
+        for id in device_ids {
+            self.hold(&mut holds, resource_name, id);
+        }
+        Ok(())
+    }
+
+    // Brings holds in line with the assignments kubelet reports: picks up allocations made before
+    // we started and releases devices whose pods are gone.
+    fn reconcile(&self, assignments: &[podresources::DeviceAssignment]) {
+        let in_use: BTreeMap<&str, &str> = assignments
+            .iter()
+            .filter(|a| self.resources.contains_key(&a.resource_name))
+            .map(|a| (a.device_id.as_str(), a.resource_name.as_str()))
+            .collect();
+
+        let mut holds = self.holds.lock().unwrap();
+        for (id, resource_name) in &in_use {
+            self.hold(&mut holds, resource_name, id);
+        }
+
+        let released: BTreeSet<String> = holds
+            .iter()
+            .filter(|(id, hold)| {
+                !in_use.contains_key(id.as_str()) && hold.last_seen.elapsed() >= CLAIM_GRACE
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in released {
+            if let Some(hold) = holds.remove(&id) {
+                self.mask(&hold.resource_name, &id, false);
+            }
+        }
+    }
+
+    pub fn spawn_reconciler(
+        self: Arc<Self>,
+        pod_resources_socket: PathBuf,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                if *shutdown.borrow() {
+                    break;
+                }
+
+                match podresources::list_assignments(&pod_resources_socket).await {
+                    Ok(assignments) => self.reconcile(&assignments),
+                    Err(err) => eprintln!("failed to list pod resources: {err}"),
+                }
+
+                select! {
+                    _ = sleep(POLL_INTERVAL) => {},
+                    changed = shutdown.changed() => {
+                        if changed.is_err() || *shutdown.borrow() {
+                            break;
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{k8s, store::DeviceStore};
+
+    fn resources(names: &[&str], ids: &[&str]) -> BTreeMap<String, SharedDevices> {
+        names
+            .iter()
+            .map(|name| {
+                let devices = ids
+                    .iter()
+                    .map(|id| {
+                        let dev = k8s::Device {
+                            id: id.to_string(),
+                            health: "Healthy".to_string(),
+                            topology: None,
+                        };
+                        (id.to_string(), dev)
+                    })
+                    .collect();
+                (name.to_string(), Arc::new(DeviceStore::new(devices)))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn failed_claim_holds_nothing() {
+        let resources = resources(&["nvidia.com/gpu", "example.com/a100"], &["gpu=0", "gpu=1"]);
+        let health = |name: &str, id: &str| resources[name].snapshot().devices()[id].health.clone();
+        let occupancy = Occupancy::new(resources.clone());
+
+        occupancy
+            .claim("example.com/a100", &["gpu=1".to_string()])
+            .unwrap();
+        assert_eq!(health("nvidia.com/gpu", "gpu=1"), "Unhealthy");
+
+        // gpu=1 is taken, so gpu=0 must stay available under both names.
+        let ids = ["gpu=0".to_string(), "gpu=1".to_string()];
+        assert!(occupancy.claim("nvidia.com/gpu", &ids).is_err());
+        assert_eq!(health("nvidia.com/gpu", "gpu=0"), "Healthy");
+        assert_eq!(health("example.com/a100", "gpu=0"), "Healthy");
+        occupancy
+            .claim("example.com/a100", &["gpu=0".to_string()])
+            .unwrap();
+    }
+}
@@ -0,0 +1,51 @@
+use tokio::net::unix::UCred;
+use tonic::{service::Interceptor, transport::server::UdsConnectInfo, Request, Status};
+
+/// Which local processes may call our unix sockets, checked against the SO_PEERCRED credentials
+/// of each connection. Without this any process that can reach the socket could call Allocate.
+#[derive(Clone, Debug)]
+pub struct PeerPolicy {
+    uids: Vec<u32>,
+    gids: Vec<u32>,
+}
+
+impl Default for PeerPolicy {
+    fn default() -> Self {
+        Self::new(vec![0], Vec::new())
+    }
+}
+
+impl PeerPolicy {
+    pub fn new(uids: Vec<u32>, gids: Vec<u32>) -> Self {
+        Self { uids, gids }
+    }
+
+    fn allows(&self, cred: &UCred) -> bool {
+        self.uids.contains(&cred.uid()) || self.gids.contains(&cred.gid())
+    }
+}
+
+impl Interceptor for PeerPolicy {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let cred = request
+            .extensions()
+            .get::<UdsConnectInfo>()
+            .and_then(|info| info.peer_cred);
+        match cred {
+            Some(cred) if self.allows(&cred) => Ok(request),
+            Some(cred) => {
+                eprintln!(
+                    "rejecting call from uid={} gid={} pid={}",
+                    cred.uid(),
+                    cred.gid(),
+                    cred.pid().map_or("?".to_string(), |pid| pid.to_string())
+                );
+                Err(Status::permission_denied(format!(
+                    "uid {} is not allowed to use this socket",
+                    cred.uid()
+                )))
+            }
+            None => Err(Status::permission_denied("peer credentials unavailable")),
+        }
+    }
+}
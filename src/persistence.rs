@@ -0,0 +1,66 @@
+use crate::gpu::normalize_bus_id;
+use std::{collections::BTreeMap, process::Command};
+
+/// Persistence mode of each GPU, keyed by PCI bus ID. Empty if nvidia-smi is unavailable.
+pub fn query() -> BTreeMap<String, bool> {
+    let Ok(output) = Command::new("nvidia-smi")
+        .args([
+            "--query-gpu=pci.bus_id,persistence_mode",
+            "--format=csv,noheader",
+        ])
+        .output()
+    else {
+        return BTreeMap::new();
+    };
+    if !output.status.success() {
+        return BTreeMap::new();
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (bus_id, mode) = line.split_once(',')?;
+            Some((normalize_bus_id(bus_id), mode.trim() == "Enabled"))
+        })
+        .collect()
+}
+
+/// Summarizes persistence mode across GPUs as "enabled", "disabled" or "mixed".
+pub fn summary(modes: &BTreeMap<String, bool>) -> Option<&'static str> {
+    if modes.is_empty() {
+        return None;
+    }
+    match modes.values().filter(|on| **on).count() {
+        0 => Some("disabled"),
+        n if n == modes.len() => Some("enabled"),
+        _ => Some("mixed"),
+    }
+}
+
+/// Enables persistence mode on all GPUs so the driver keeps them initialized while no CUDA
+/// process is running. Without it first-touch latency is high and, on some distros, device nodes
+/// come and go. Requires CAP_SYS_ADMIN, so this runs before privileges are dropped.
+pub fn enable() -> anyhow::Result<()> {
+    let output = Command::new("nvidia-smi")
+        .args(["-pm", "1"])
+        .output()
+        .map_err(|err| anyhow::anyhow!("failed to run nvidia-smi: {err}"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "nvidia-smi -pm 1 failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let modes = query();
+    let disabled: Vec<&str> = modes
+        .iter()
+        .filter(|(_, on)| !**on)
+        .map(|(bus_id, _)| bus_id.as_str())
+        .collect();
+    if !disabled.is_empty() {
+        anyhow::bail!("persistence mode still disabled on {}", disabled.join(", "));
+    }
+    println!("persistence mode enabled on {} GPUs", modes.len());
+    Ok(())
+}
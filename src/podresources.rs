@@ -0,0 +1,80 @@
+use crate::unix_channel;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tonic::transport::Channel;
+
+pub mod api {
+    tonic::include_proto!("v1");
+}
+
+pub const DEFAULT_POD_RESOURCES_SOCKET: &str = "/var/lib/kubelet/pod-resources/kubelet.sock";
+
+// Connections opened by [`connect`], keyed by socket path.
+static CONNECTED: Mutex<BTreeMap<PathBuf, Channel>> = Mutex::new(BTreeMap::new());
+
+/// A device kubelet has assigned to a running container.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DeviceAssignment {
+    pub resource_name: String,
+    pub device_id: String,
+    pub pod_namespace: String,
+    pub pod_name: String,
+}
+
+/// Connects to the PodResources API at `socket` ahead of time. kubelet's socket only accepts
+/// root, so this keeps [`list_assignments`] working after dropping privileges, until kubelet
+/// restarts.
+pub async fn connect(socket: &Path) -> anyhow::Result<()> {
+    let channel = unix_channel(socket.to_path_buf()).await?;
+    CONNECTED
+        .lock()
+        .unwrap()
+        .insert(socket.to_path_buf(), channel);
+    Ok(())
+}
+
+/// Lists device assignments of all pods on the node via kubelet's PodResources API.
+pub async fn list_assignments(socket: &Path) -> anyhow::Result<Vec<DeviceAssignment>> {
+    let connected = CONNECTED.lock().unwrap().get(socket).cloned();
+    let channel = match connected {
+        Some(channel) => channel,
+        None => unix_channel(socket.to_path_buf()).await?,
+    };
+    let mut client = api::pod_resources_lister_client::PodResourcesListerClient::new(channel);
+    let resp = client
+        .list(api::ListPodResourcesRequest {})
+        .await?
+        .into_inner();
+
+    let mut out = Vec::new();
+    for pod in resp.pod_resources {
+        for container in &pod.containers {
+            for devs in &container.devices {
+                for device_id in &devs.device_ids {
+                    out.push(DeviceAssignment {
+                        resource_name: devs.resource_name.clone(),
+                        device_id: device_id.clone(),
+                        pod_namespace: pod.namespace.clone(),
+                        pod_name: pod.name.clone(),
+                    });
+                }
+            }
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// Groups assigned device IDs by resource name.
+pub fn allocated_ids(assignments: &[DeviceAssignment]) -> BTreeMap<String, BTreeSet<String>> {
+    let mut out: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for assignment in assignments {
+        out.entry(assignment.resource_name.clone())
+            .or_default()
+            .insert(assignment.device_id.clone());
+    }
+    out
+}
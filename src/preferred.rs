@@ -0,0 +1,122 @@
+use crate::k8s;
+use std::collections::{BTreeMap, HashSet};
+
+fn numa_node(dev: Option<&k8s::Device>) -> Option<i64> {
+    dev.and_then(crate::numa_node)
+}
+
+/// Picks `size` devices from `available`, keeping the allocation on as few NUMA nodes as
+/// possible so that GPUs and their companion resources (e.g. GPUDirect NICs) can be aligned by
+/// the kubelet topology manager.
+pub fn preferred_devices(
+    devices: &BTreeMap<String, k8s::Device>,
+    available: &[String],
+    must_include: &[String],
+    size: usize,
+) -> Vec<String> {
+    let mut chosen: Vec<String> = Vec::with_capacity(size);
+    // Membership is checked through a set: with MIG and time-slicing `available` can hold
+    // thousands of IDs.
+    let mut seen: HashSet<&str> = HashSet::with_capacity(must_include.len());
+    for id in must_include {
+        if seen.insert(id) {
+            chosen.push(id.clone());
+        }
+    }
+
+    let mut by_node: BTreeMap<Option<i64>, Vec<&String>> = BTreeMap::new();
+    for id in available {
+        if !seen.contains(id.as_str()) {
+            by_node
+                .entry(numa_node(devices.get(id)))
+                .or_default()
+                .push(id);
+        }
+    }
+
+    // Nodes already used by required devices come first, then the smallest node that can satisfy
+    // the rest on its own (to limit fragmentation), then the remaining nodes largest first.
+    let pinned: HashSet<Option<i64>> = chosen.iter().map(|id| numa_node(devices.get(id))).collect();
+    let remaining = size.saturating_sub(chosen.len());
+    let mut order: Vec<Option<i64>> = by_node.keys().copied().collect();
+    order.sort_by_key(|node| {
+        let count = by_node[node].len();
+        let fits = count >= remaining;
+        (
+            !pinned.contains(node),
+            !fits,
+            if fits { count } else { usize::MAX - count },
+        )
+    });
+
+    for node in order {
+        for id in &by_node[&node] {
+            if chosen.len() >= size {
+                return chosen;
+            }
+            chosen.push((*id).clone());
+        }
+    }
+
+    chosen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two GPUs on node 0, three on node 1, one without NUMA information.
+    fn devices() -> BTreeMap<String, k8s::Device> {
+        [
+            ("gpu=0", Some(0)),
+            ("gpu=1", Some(0)),
+            ("gpu=2", Some(1)),
+            ("gpu=3", Some(1)),
+            ("gpu=4", Some(1)),
+            ("gpu=5", None),
+        ]
+        .into_iter()
+        .map(|(id, node)| {
+            (
+                id.to_string(),
+                k8s::Device {
+                    id: id.to_string(),
+                    health: "Healthy".to_string(),
+                    topology: crate::topology_info(node),
+                },
+            )
+        })
+        .collect()
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn prefers_numa_local_allocations() {
+        const ALL: &[&str] = &["gpu=0", "gpu=1", "gpu=2", "gpu=3", "gpu=4", "gpu=5"];
+        let devices = devices();
+        let cases: &[(&[&str], &[&str], usize, &[&str])] = &[
+            // the smallest node that fits the request on its own
+            (ALL, &[], 2, &["gpu=0", "gpu=1"]),
+            (ALL, &[], 3, &["gpu=2", "gpu=3", "gpu=4"]),
+            // required devices pin their node
+            (ALL, &["gpu=3"], 2, &["gpu=3", "gpu=2"]),
+            // nothing fits on one node: largest nodes first
+            (ALL, &[], 5, &["gpu=2", "gpu=3", "gpu=4", "gpu=0", "gpu=1"]),
+            // only what is available
+            (&["gpu=1", "gpu=2"], &[], 1, &["gpu=1"]),
+            (&["gpu=1"], &[], 2, &["gpu=1"]),
+            // duplicate required IDs are chosen once
+            (ALL, &["gpu=0", "gpu=0"], 1, &["gpu=0"]),
+        ];
+        for (available, must_include, size, want) in cases {
+            assert_eq!(
+                preferred_devices(&devices, &ids(available), &ids(must_include), *size),
+                ids(want),
+                "available {available:?}, must include {must_include:?}, size {size}"
+            );
+        }
+    }
+}
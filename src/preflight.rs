@@ -0,0 +1,308 @@
+use crate::{gpu, versions};
+use clap::ValueEnum;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const DEFAULT_CONTAINERD_CONFIG: &str = "/etc/containerd/config.toml";
+pub const DEFAULT_CRIO_CONFIG_DIR: &str = "/etc/crio";
+
+/// What to do at startup when the container runtime does not have CDI injection enabled.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// don't check
+    Off,
+    /// log a warning and advertise anyway
+    Warn,
+    /// refuse to start
+    Fail,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+// CRI plugin tables holding enable_cdi in containerd 1.x (config version 2) and 2.x (version 3).
+const CONTAINERD_CRI_TABLES: &[&str] = &[
+    r#"plugins."io.containerd.grpc.v1.cri""#,
+    r#"plugins."io.containerd.cri.v1.runtime""#,
+];
+
+// A table header such as `[plugins.'io.containerd.grpc.v1.cri']`, spelled the way callers of
+// toml_value name tables.
+fn table_name(header: &str) -> String {
+    header
+        .trim_matches(['[', ']'])
+        .replace('\'', "\"")
+        .split('.')
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// Value of the last `key = value` assignment in `table` of a TOML file ("" for the top level).
+// Good enough for the few scalar settings we look at without pulling in a TOML parser.
+fn toml_value(contents: &str, table: &str, key: &str) -> Option<String> {
+    let mut current = String::new();
+    let mut value = None;
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.starts_with('[') {
+            current = table_name(line);
+        } else if let Some((k, v)) = line.split_once('=')
+            && current == table
+            && k.trim() == key
+        {
+            value = Some(v.trim().trim_matches('"').to_string());
+        }
+    }
+    value
+}
+
+fn containerd_check(path: &Path) -> Option<Check> {
+    let contents = fs::read_to_string(path).ok()?;
+    let version: u32 = toml_value(&contents, "", "version")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1);
+    let enable_cdi = CONTAINERD_CRI_TABLES
+        .iter()
+        .find_map(|table| toml_value(&contents, table, "enable_cdi"));
+    let check = match enable_cdi.as_deref() {
+        Some("true") => Check::new(
+            "runtime CDI",
+            Status::Ok,
+            format!("containerd: enable_cdi = true in {}", path.display()),
+        ),
+        Some(_) => Check::new(
+            "runtime CDI",
+            Status::Fail,
+            format!("containerd: enable_cdi = false in {}", path.display()),
+        ),
+        // containerd 2.x (config version 3) enables CDI by default, 1.x does not.
+        None if version >= 3 => Check::new(
+            "runtime CDI",
+            Status::Ok,
+            format!(
+                "containerd 2.x config {} (CDI on by default)",
+                path.display()
+            ),
+        ),
+        None => Check::new(
+            "runtime CDI",
+            Status::Fail,
+            format!(
+                "containerd: enable_cdi is not set in {} (containerd 1.x defaults to false)",
+                path.display()
+            ),
+        ),
+    };
+    Some(check)
+}
+
+fn crio_check(dir: &Path) -> Option<Check> {
+    let mut files = vec![dir.join("crio.conf")];
+    if let Ok(entries) = fs::read_dir(dir.join("crio.conf.d")) {
+        let mut dropins: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+        dropins.sort();
+        files.extend(dropins);
+    }
+    let configs: Vec<String> = files
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .collect();
+    if configs.is_empty() {
+        return None;
+    }
+    // CRI-O has injected CDI devices by default since 1.23; only a custom spec dir list can hide
+    // ours from it.
+    let spec_dirs = configs
+        .iter()
+        .rev()
+        .find_map(|contents| toml_value(contents, "crio.runtime", "cdi_spec_dirs"));
+    Some(match spec_dirs {
+        Some(dirs) => Check::new(
+            "runtime CDI",
+            Status::Warn,
+            format!("CRI-O: CDI enabled, custom cdi_spec_dirs = {dirs}"),
+        ),
+        None => Check::new("runtime CDI", Status::Ok, "CRI-O: CDI enabled by default"),
+    })
+}
+
+/// Checks whether containerd or CRI-O on this node will inject the CDI devices we hand out.
+/// Needs the runtime's configuration mounted into the plugin's container.
+pub fn runtime_check(containerd_config: &Path, crio_config_dir: &Path) -> Check {
+    containerd_check(containerd_config)
+        .or_else(|| crio_check(crio_config_dir))
+        .unwrap_or_else(|| {
+            Check::new(
+                "runtime CDI",
+                Status::Warn,
+                format!(
+                    "no containerd ({}) or CRI-O ({}) configuration found",
+                    containerd_config.display(),
+                    crio_config_dir.display()
+                ),
+            )
+        })
+}
+
+pub fn driver_check() -> Check {
+    match versions::detect_driver_version() {
+        Some(version) => Check::new("driver", Status::Ok, format!("NVIDIA driver {version}")),
+        None if gpu::driver_loaded() => Check::new("driver", Status::Ok, "NVIDIA driver loaded"),
+        None => Check::new("driver", Status::Fail, "NVIDIA driver is not loaded"),
+    }
+}
+
+pub fn cdi_spec_check(resource_name: &str, cdi_spec_dirs: &[PathBuf]) -> Check {
+    match versions::detect_cdi_spec_version(resource_name, cdi_spec_dirs) {
+        Some(version) => Check::new(
+            "CDI spec",
+            Status::Ok,
+            format!("spec for {resource_name} found (cdiVersion {version})"),
+        ),
+        None => Check::new(
+            "CDI spec",
+            Status::Fail,
+            format!("no CDI spec of kind {resource_name} in the CDI spec dirs"),
+        ),
+    }
+}
+
+pub fn devices_check(count: usize) -> Check {
+    if count == 0 {
+        Check::new("devices", Status::Fail, "no devices discovered")
+    } else {
+        Check::new("devices", Status::Ok, format!("{count} devices discovered"))
+    }
+}
+
+/// One line per check, as printed by [`print_report`].
+pub fn format_report(checks: &[Check]) -> String {
+    checks
+        .iter()
+        .map(|check| {
+            let status = match check.status {
+                Status::Ok => "ok",
+                Status::Warn => "WARN",
+                Status::Fail => "FAIL",
+            };
+            format!("{status:<5} {:<12} {}\n", check.name, check.detail)
+        })
+        .collect()
+}
+
+/// Prints `checks` and fails if any of them failed.
+pub fn print_report(checks: &[Check]) -> anyhow::Result<()> {
+    print!("{}", format_report(checks));
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failed > 0 {
+        anyhow::bail!("{failed} check(s) failed");
+    }
+    Ok(())
+}
+
+/// Applies `policy` to the runtime CDI check at startup.
+pub fn enforce(policy: Policy, check: &Check) -> anyhow::Result<()> {
+    match (check.status, policy) {
+        (_, Policy::Off) => {}
+        (Status::Ok, _) => println!("{}", check.detail),
+        (Status::Warn, _) => eprintln!("warning: {}", check.detail),
+        (Status::Fail, Policy::Fail) => anyhow::bail!(
+            "container runtime will not inject CDI devices: {} (pods would fail at creation)",
+            check.detail
+        ),
+        (Status::Fail, Policy::Warn) => eprintln!(
+            "warning: container runtime will not inject CDI devices: {}; pods using them will fail at creation",
+            check.detail
+        ),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTAINERD_V2: &str = r#"
+version = 2
+
+[plugins]
+  [plugins."io.containerd.grpc.v1.cri"]
+    enable_cdi = true   # needed by the device plugin
+    [plugins."io.containerd.grpc.v1.cri".containerd]
+      enable_cdi = false
+"#;
+
+    const CONTAINERD_V3: &str = r#"
+version = 3
+
+[plugins.'io.containerd.cri.v1.runtime']
+  enable_cdi = false
+"#;
+
+    const CRIO: &str = r#"
+[crio]
+cdi_spec_dirs = ["/ignored"]
+
+[crio.runtime]
+cdi_spec_dirs = ["/etc/cdi"]
+"#;
+
+    #[test]
+    fn reads_values_from_their_table() {
+        let cases = [
+            (CONTAINERD_V2, "", "version", Some("2")),
+            (
+                CONTAINERD_V2,
+                r#"plugins."io.containerd.grpc.v1.cri""#,
+                "enable_cdi",
+                Some("true"),
+            ),
+            (CONTAINERD_V2, "plugins", "enable_cdi", None),
+            (CONTAINERD_V3, "", "version", Some("3")),
+            (
+                CONTAINERD_V3,
+                r#"plugins."io.containerd.cri.v1.runtime""#,
+                "enable_cdi",
+                Some("false"),
+            ),
+            (CONTAINERD_V3, "", "enable_cdi", None),
+            (
+                CRIO,
+                "crio.runtime",
+                "cdi_spec_dirs",
+                Some(r#"["/etc/cdi"]"#),
+            ),
+            ("", "", "version", None),
+        ];
+        for (contents, table, key, want) in cases {
+            assert_eq!(
+                toml_value(contents, table, key).as_deref(),
+                want,
+                "{table}.{key}"
+            );
+        }
+    }
+}
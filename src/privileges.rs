@@ -0,0 +1,70 @@
+use caps::CapSet;
+use std::{fs, io, os::unix::fs::PermissionsExt, path::Path};
+
+fn check(ret: libc::c_int, what: &str) -> anyhow::Result<()> {
+    if ret != 0 {
+        anyhow::bail!("{what} failed: {}", io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Whether the process still runs as root.
+pub fn privileged() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Keeps execve from ever granting privileges (setuid binaries, file capabilities). The flag is
+/// per-thread and inherited, so this must run before the tokio worker threads are started.
+pub fn set_no_new_privs() -> anyhow::Result<()> {
+    unsafe {
+        check(
+            libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0),
+            "PR_SET_NO_NEW_PRIVS",
+        )
+    }
+}
+
+/// Hands `dir` to group `gid` with group write access, so that sockets and state files in it can
+/// still be replaced after [`drop_privileges`]. Only meant for directories the plugin owns.
+pub fn share_directory(dir: &Path, gid: u32) -> anyhow::Result<()> {
+    std::os::unix::fs::chown(dir, None, Some(gid))
+        .map_err(|err| anyhow::anyhow!("failed to chown {}: {err}", dir.display()))?;
+    let mut perms = fs::metadata(dir)?.permissions();
+    perms.set_mode(perms.mode() | 0o070);
+    fs::set_permissions(dir, perms)?;
+    Ok(())
+}
+
+/// Switches the whole process to `uid`/`gid` without any capabilities.
+///
+/// glibc applies set*id calls to every thread and the kernel clears a thread's capabilities when
+/// it leaves uid 0, so this is safe once the runtime is running. Sockets and other handles opened
+/// before stay usable.
+pub fn drop_privileges(uid: u32, gid: u32) -> anyhow::Result<()> {
+    if !privileged() {
+        anyhow::bail!("dropping privileges requires starting as root");
+    }
+
+    // The bounding set is per-thread and can only shrink while we hold CAP_SETPCAP; other threads
+    // rely on no_new_privs instead.
+    for cap in caps::all() {
+        caps::drop(None, CapSet::Bounding, cap)?;
+    }
+    caps::clear(None, CapSet::Inheritable)?;
+
+    unsafe {
+        check(libc::setgroups(1, &gid), "setgroups")?;
+        check(libc::setgid(gid), "setgid")?;
+        check(libc::setuid(uid), "setuid")?;
+    }
+
+    if !caps::read(None, CapSet::Permitted)?.is_empty() {
+        anyhow::bail!("capabilities survived switching to uid {uid}");
+    }
+    if unsafe { libc::setuid(0) } == 0 {
+        anyhow::bail!("regained root after dropping privileges");
+    }
+
+    println!("dropped privileges: uid={uid} gid={gid}");
+    Ok(())
+}
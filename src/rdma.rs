@@ -0,0 +1,96 @@
+use crate::{gpu::pci_numa_node, k8s, topology_info};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+const INFINIBAND_CLASS_DIR: &str = "/sys/class/infiniband";
+const MELLANOX_VENDOR_ID: &str = "0x15b3";
+
+/// An RDMA-capable Mellanox/ConnectX device as exposed under /sys/class/infiniband.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RdmaDevice {
+    pub name: String,
+    pub pci_bus_id: String,
+    pub numa_node: Option<i64>,
+}
+
+pub fn discover_rdma_devices() -> Vec<RdmaDevice> {
+    let Ok(entries) = fs::read_dir(INFINIBAND_CLASS_DIR) else {
+        return Vec::new();
+    };
+
+    let mut devs: Vec<RdmaDevice> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let pci_dev = entry.path().join("device");
+            let vendor = fs::read_to_string(pci_dev.join("vendor")).ok()?;
+            if vendor.trim() != MELLANOX_VENDOR_ID {
+                return None;
+            }
+            let pci_bus_id = fs::canonicalize(&pci_dev)
+                .ok()?
+                .file_name()?
+                .to_string_lossy()
+                .into_owned();
+            Some(RdmaDevice {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                numa_node: pci_numa_node(&pci_bus_id),
+                pci_bus_id,
+            })
+        })
+        .collect();
+    devs.sort_by(|a, b| a.name.cmp(&b.name));
+    devs
+}
+
+/// Builds the companion GPUDirect resource from RDMA devices sharing a NUMA node with a GPU.
+/// Device IDs double as CDI names, so the resource name must match the mofed/ib CDI kind.
+pub fn gpudirect_devices(
+    resource_name: &str,
+    gpu_numa_nodes: &BTreeSet<Option<i64>>,
+) -> BTreeMap<String, k8s::Device> {
+    let mut devs = BTreeMap::new();
+
+    for rdma in discover_rdma_devices() {
+        // Without NUMA information we cannot tell whether a NIC is local to a GPU; keep it.
+        if rdma.numa_node.is_some() && !gpu_numa_nodes.contains(&rdma.numa_node) {
+            println!(
+                "skipping RDMA device {} ({}): no GPU on NUMA node {:?}",
+                rdma.name, rdma.pci_bus_id, rdma.numa_node
+            );
+            continue;
+        }
+
+        let id = format!("{resource_name}={}", rdma.name);
+        devs.insert(
+            id.clone(),
+            k8s::Device {
+                id,
+                health: "Healthy".to_string(),
+                topology: topology_info(rdma.numa_node),
+            },
+        );
+    }
+
+    if devs.is_empty() {
+        eprintln!(
+            "warning: no RDMA devices co-located with GPUs found under {}",
+            Path::new(INFINIBAND_CLASS_DIR).display()
+        );
+    }
+
+    devs
+}
+
+/// Groups the CDI names of `devices` by NUMA node, for injection alongside GPUs on that node.
+pub fn by_numa_node(devices: &BTreeMap<String, k8s::Device>) -> BTreeMap<Option<i64>, Vec<String>> {
+    let mut out: BTreeMap<Option<i64>, Vec<String>> = BTreeMap::new();
+    for (id, dev) in devices {
+        out.entry(crate::numa_node(dev))
+            .or_default()
+            .push(id.clone());
+    }
+    out
+}
@@ -0,0 +1,423 @@
+use crate::{podresources, store::HealthReason, SharedDevices};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{process::Command, select, sync::watch, task::JoinHandle, time::sleep};
+
+pub const RETIREMENT_STATE_FILE_NAME: &str = "retirements.json";
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetirementState {
+    Draining,
+    Retired,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Retirement {
+    pub resource_name: String,
+    pub device_id: String,
+    /// UUID of the GPU behind `device_id`. GPU device IDs are numbered by position, so removing
+    /// a GPU renumbers the others; retirements are matched to devices by UUID when loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu_uuid: Option<String>,
+    pub state: RetirementState,
+    /// Pods (namespace/name) still using the device, as of the last PodResources poll.
+    #[serde(skip)]
+    pub pods: Vec<String>,
+}
+
+/// Tracks devices marked for retirement. Draining devices are reported unhealthy so kubelet stops
+/// handing them to new pods; once PodResources shows no pod using one, it is dropped from the
+/// advertised list for good. State is persisted so retirements survive plugin restarts.
+pub struct Retirements {
+    state_file: PathBuf,
+    resources: BTreeMap<String, SharedDevices>,
+    // GPU UUID of each positional device ID.
+    gpu_uuids: BTreeMap<String, String>,
+    hook: Option<PathBuf>,
+    entries: Mutex<BTreeMap<(String, String), Retirement>>,
+    // Retired GPUs that are no longer installed; kept so they stay retired if they come back.
+    absent: Vec<Retirement>,
+}
+
+impl Retirements {
+    pub fn load(
+        state_file: PathBuf,
+        resources: BTreeMap<String, SharedDevices>,
+        gpu_uuids: BTreeMap<String, String>,
+        hook: Option<PathBuf>,
+    ) -> anyhow::Result<Arc<Self>> {
+        let saved: Vec<Retirement> = match fs::read_to_string(&state_file) {
+            Ok(raw) => serde_json::from_str(&raw).map_err(|err| {
+                anyhow::anyhow!("failed to parse {}: {err}", state_file.display())
+            })?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => anyhow::bail!("failed to read {}: {err}", state_file.display()),
+        };
+
+        let mut retirements = Self {
+            state_file,
+            resources,
+            gpu_uuids,
+            hook,
+            entries: Mutex::new(BTreeMap::new()),
+            absent: Vec::new(),
+        };
+        {
+            let mut entries = retirements.entries.lock().unwrap();
+            for mut entry in saved {
+                match &entry.gpu_uuid {
+                    Some(uuid) => {
+                        let current = retirements
+                            .gpu_uuids
+                            .iter()
+                            .find(|(_, installed)| *installed == uuid);
+                        let Some((device_id, _)) = current else {
+                            println!("retired GPU {uuid} is no longer installed");
+                            retirements.absent.push(entry);
+                            continue;
+                        };
+                        entry.device_id = device_id.clone();
+                    }
+                    // Written before UUIDs were recorded; the device ID is all there is.
+                    None => entry.gpu_uuid = retirements.gpu_uuids.get(&entry.device_id).cloned(),
+                }
+                println!(
+                    "restoring retirement of {} ({:?})",
+                    entry.device_id, entry.state
+                );
+                retirements.apply(&entry);
+                entries.insert(
+                    (entry.resource_name.clone(), entry.device_id.clone()),
+                    entry,
+                );
+            }
+        }
+        Ok(Arc::new(retirements))
+    }
+
+    // Reflects a retirement in the advertised device lists. Aliases and per-model resources
+    // advertise a GPU under its original device ID, so this covers every name it is offered under.
+    fn apply(&self, entry: &Retirement) {
+        for devices in self.resources.values() {
+            match entry.state {
+                RetirementState::Draining => {
+                    devices.set_unhealthy(&entry.device_id, HealthReason::Retiring, true);
+                }
+                RetirementState::Retired => {
+                    devices.update(|devs| devs.remove(&entry.device_id).is_some());
+                }
+            }
+        }
+    }
+
+    fn persist(&self, entries: &BTreeMap<(String, String), Retirement>) -> anyhow::Result<()> {
+        if let Some(dir) = self.state_file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let saved: Vec<&Retirement> = entries.values().chain(&self.absent).collect();
+        let tmp = self.state_file.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&saved)?)?;
+        fs::rename(&tmp, &self.state_file)?;
+        Ok(())
+    }
+
+    pub fn retire(&self, resource_name: &str, device_id: &str) -> anyhow::Result<Retirement> {
+        let Some(devices) = self.resources.get(resource_name) else {
+            anyhow::bail!("unknown resource {resource_name}");
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(existing) = entries.values().find(|entry| entry.device_id == device_id) {
+            return Ok(existing.clone());
+        }
+        if !devices.snapshot().contains(device_id) {
+            anyhow::bail!("device {device_id} is not advertised for {resource_name}");
+        }
+
+        let entry = Retirement {
+            resource_name: resource_name.to_string(),
+            device_id: device_id.to_string(),
+            gpu_uuid: self.gpu_uuids.get(device_id).cloned(),
+            state: RetirementState::Draining,
+            pods: Vec::new(),
+        };
+        self.apply(&entry);
+        entries.insert(
+            (resource_name.to_string(), device_id.to_string()),
+            entry.clone(),
+        );
+        self.persist(&entries)?;
+        println!("retiring {device_id}: no longer offered to new pods");
+        Ok(entry)
+    }
+
+    pub fn list(&self) -> Vec<Retirement> {
+        self.entries.lock().unwrap().values().cloned().collect()
+    }
+
+    // Advances draining devices whose last pod has gone and returns the newly retired ones.
+    fn advance(&self, assignments: &[podresources::DeviceAssignment]) -> Vec<Retirement> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut retired = Vec::new();
+
+        for entry in entries.values_mut() {
+            if entry.state != RetirementState::Draining {
+                continue;
+            }
+            // Pods may hold the device through any of the names it is advertised under.
+            entry.pods = assignments
+                .iter()
+                .filter(|a| a.device_id == entry.device_id)
+                .map(|a| format!("{}/{}", a.pod_namespace, a.pod_name))
+                .collect();
+            entry.pods.dedup();
+            if entry.pods.is_empty() {
+                entry.state = RetirementState::Retired;
+                retired.push(entry.clone());
+            }
+        }
+
+        for entry in &retired {
+            self.apply(entry);
+            println!(
+                "retired {}: removed from {}",
+                entry.device_id, entry.resource_name
+            );
+        }
+        if !retired.is_empty()
+            && let Err(err) = self.persist(&entries)
+        {
+            eprintln!("failed to persist retirement state: {err}");
+        }
+        retired
+    }
+
+    async fn run_hook(&self, hook: &Path, entry: &Retirement) {
+        let status = Command::new(hook)
+            .env("RESOURCE_NAME", &entry.resource_name)
+            .env("DEVICE_ID", &entry.device_id)
+            .status()
+            .await;
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!(
+                "retirement hook {} failed for {}: {status}",
+                hook.display(),
+                entry.device_id
+            ),
+            Err(err) => eprintln!("failed to run retirement hook {}: {err}", hook.display()),
+        }
+    }
+
+    pub fn spawn_watcher(
+        self: Arc<Self>,
+        pod_resources_socket: PathBuf,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                if *shutdown.borrow() {
+                    break;
+                }
+
+                let draining = self
+                    .list()
+                    .iter()
+                    .any(|entry| entry.state == RetirementState::Draining);
+                if draining {
+                    match podresources::list_assignments(&pod_resources_socket).await {
+                        Ok(assignments) => {
+                            for entry in self.advance(&assignments) {
+                                if let Some(hook) = &self.hook {
+                                    self.run_hook(hook, &entry).await;
+                                }
+                            }
+                        }
+                        Err(err) => eprintln!("failed to list pod resources: {err}"),
+                    }
+                }
+
+                select! {
+                    _ = sleep(POLL_INTERVAL) => {},
+                    changed = shutdown.changed() => {
+                        if changed.is_err() || *shutdown.borrow() {
+                            break;
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{k8s, store::DeviceStore};
+
+    const GPU: &str = "nvidia.com/gpu";
+
+    fn state_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("retirement-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join(RETIREMENT_STATE_FILE_NAME)
+    }
+
+    // Loads retirements for GPUs with the given UUIDs, numbered in order.
+    fn load(state_file: &Path, uuids: &[&str]) -> (Arc<Retirements>, SharedDevices) {
+        let ids: Vec<String> = (0..uuids.len()).map(|idx| format!("{GPU}={idx}")).collect();
+        let devices = Arc::new(DeviceStore::new(
+            ids.iter()
+                .map(|id| {
+                    let dev = k8s::Device {
+                        id: id.clone(),
+                        health: "Healthy".to_string(),
+                        topology: None,
+                    };
+                    (id.clone(), dev)
+                })
+                .collect(),
+        ));
+        let gpu_uuids = ids
+            .into_iter()
+            .zip(uuids.iter().map(|uuid| uuid.to_string()))
+            .collect();
+        let retirements = Retirements::load(
+            state_file.to_path_buf(),
+            BTreeMap::from([(GPU.to_string(), devices.clone())]),
+            gpu_uuids,
+            None,
+        )
+        .unwrap();
+        (retirements, devices)
+    }
+
+    fn assignment(device_id: &str, pod_name: &str) -> podresources::DeviceAssignment {
+        podresources::DeviceAssignment {
+            resource_name: GPU.to_string(),
+            device_id: device_id.to_string(),
+            pod_namespace: "default".to_string(),
+            pod_name: pod_name.to_string(),
+        }
+    }
+
+    fn advertised(devices: &SharedDevices) -> Vec<String> {
+        devices.snapshot().devices().keys().cloned().collect()
+    }
+
+    #[test]
+    fn retires_once_the_last_pod_is_gone() {
+        let state_file = state_file("advance");
+        let (retirements, devices) = load(&state_file, &["GPU-a", "GPU-b"]);
+
+        retirements.retire(GPU, "nvidia.com/gpu=1").unwrap();
+        assert!(devices
+            .snapshot()
+            .has_reason("nvidia.com/gpu=1", HealthReason::Retiring));
+
+        let steps = [
+            (
+                vec![assignment("nvidia.com/gpu=1", "a")],
+                0,
+                vec!["default/a"],
+            ),
+            (vec![assignment("nvidia.com/gpu=0", "b")], 1, vec![]),
+            (vec![], 0, vec![]),
+        ];
+        for (assignments, newly_retired, pods) in steps {
+            assert_eq!(retirements.advance(&assignments).len(), newly_retired);
+            assert_eq!(retirements.list()[0].pods, pods);
+        }
+        assert_eq!(retirements.list()[0].state, RetirementState::Retired);
+        assert_eq!(advertised(&devices), ["nvidia.com/gpu=0"]);
+    }
+
+    #[test]
+    fn retires_under_every_alias() {
+        let (_, gpus) = load(&state_file("alias-unused"), &["GPU-a", "GPU-b"]);
+        let alias = Arc::new(DeviceStore::new(gpus.snapshot().devices().clone()));
+        let retirements = Retirements::load(
+            state_file("alias"),
+            BTreeMap::from([
+                (GPU.to_string(), gpus.clone()),
+                ("nvidia.com/a100".to_string(), alias.clone()),
+            ]),
+            BTreeMap::new(),
+            None,
+        )
+        .unwrap();
+
+        retirements
+            .retire("nvidia.com/a100", "nvidia.com/gpu=1")
+            .unwrap();
+        for devices in [&gpus, &alias] {
+            assert!(devices
+                .snapshot()
+                .has_reason("nvidia.com/gpu=1", HealthReason::Retiring));
+        }
+
+        // Still in use through the other name.
+        assert!(retirements
+            .advance(&[assignment("nvidia.com/gpu=1", "a")])
+            .is_empty());
+        assert_eq!(retirements.advance(&[]).len(), 1);
+        for devices in [&gpus, &alias] {
+            assert_eq!(advertised(devices), ["nvidia.com/gpu=0"]);
+        }
+    }
+
+    #[test]
+    fn restores_retirements_by_gpu_uuid() {
+        let state_file = state_file("restore");
+        let (retirements, _) = load(&state_file, &["GPU-a", "GPU-b", "GPU-c"]);
+        retirements.retire(GPU, "nvidia.com/gpu=1").unwrap();
+        retirements.advance(&[]);
+
+        // Installed GPUs by UUID after each restart, and the device IDs left advertised.
+        let restarts = [
+            (
+                vec!["GPU-a", "GPU-b", "GPU-c"],
+                vec!["nvidia.com/gpu=0", "nvidia.com/gpu=2"],
+            ),
+            // The retired GPU was pulled and GPU-c became nvidia.com/gpu=1.
+            (
+                vec!["GPU-a", "GPU-c"],
+                vec!["nvidia.com/gpu=0", "nvidia.com/gpu=1"],
+            ),
+            // It was put back in another slot.
+            (
+                vec!["GPU-b", "GPU-a", "GPU-c"],
+                vec!["nvidia.com/gpu=1", "nvidia.com/gpu=2"],
+            ),
+        ];
+        for (uuids, want) in restarts {
+            let (_, devices) = load(&state_file, &uuids);
+            assert_eq!(advertised(&devices), want, "{uuids:?}");
+        }
+    }
+
+    #[test]
+    fn restores_retirements_saved_without_uuid() {
+        let state_file = state_file("legacy");
+        fs::create_dir_all(state_file.parent().unwrap()).unwrap();
+        fs::write(
+            &state_file,
+            r#"[{"resource_name":"nvidia.com/gpu","device_id":"nvidia.com/gpu=0","state":"draining"}]"#,
+        )
+        .unwrap();
+
+        let (retirements, devices) = load(&state_file, &["GPU-a", "GPU-b"]);
+        assert!(devices
+            .snapshot()
+            .has_reason("nvidia.com/gpu=0", HealthReason::Retiring));
+        assert_eq!(retirements.list()[0].gpu_uuid.as_deref(), Some("GPU-a"));
+    }
+}
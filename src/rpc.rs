@@ -0,0 +1,33 @@
+use std::time::Duration;
+use tonic::transport::Server;
+use tower::{
+    layer::util::{Identity, Stack},
+    limit::GlobalConcurrencyLimitLayer,
+};
+
+/// Bounds on the work a gRPC server takes on, so a hung backend call cannot pile up tasks.
+#[derive(Clone, Copy, Debug)]
+pub struct RpcLimits {
+    /// Requests handled at once; further requests wait for a slot.
+    pub max_concurrent: usize,
+    /// Deadline for a unary call, or for a streaming call to return its stream. Time spent
+    /// waiting for a slot counts towards it.
+    pub timeout: Duration,
+}
+
+impl Default for RpcLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 64,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RpcLimits {
+    pub fn server(&self) -> Server<Stack<GlobalConcurrencyLimitLayer, Identity>> {
+        Server::builder()
+            .timeout(self.timeout)
+            .layer(GlobalConcurrencyLimitLayer::new(self.max_concurrent))
+    }
+}
@@ -0,0 +1,385 @@
+use landlock::{
+    path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus,
+    ABI,
+};
+use seccompiler::{SeccompAction, SeccompFilter, SeccompRule, TargetArch};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+// Driver, PCI and RDMA information, our own socket table, and service account credentials for
+// the Kubernetes API.
+const READ_ONLY_PATHS: &[&str] = &[
+    "/proc/driver/nvidia",
+    "/proc/net/unix",
+    "/sys/bus/pci",
+    "/sys/devices",
+    "/sys/class/infiniband",
+    "/sys/kernel/iommu_groups",
+    "/dev/vfio",
+    "/var/run/secrets",
+    "/run/secrets",
+];
+
+// Opened by std::process::Command for the stdin of every helper it runs.
+const DEVICE_PATHS: &[&str] = &["/dev/null"];
+
+// Shared libraries of helper binaries. They are only mapped, never executed directly.
+const LIBRARY_PATHS: &[&str] = &[
+    "/etc/ld.so.cache",
+    "/lib",
+    "/lib64",
+    "/usr/lib",
+    "/usr/lib64",
+    "/nix/store",
+    "/run/opengl-driver/lib",
+];
+
+// Everything the plugin and the helpers it runs (nvidia-smi, hooks) call. Anything else fails
+// with EPERM.
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_pread64,
+    libc::SYS_pwrite64,
+    libc::SYS_openat,
+    libc::SYS_close,
+    libc::SYS_close_range,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_statfs,
+    libc::SYS_fstatfs,
+    libc::SYS_lseek,
+    libc::SYS_getdents64,
+    libc::SYS_readlinkat,
+    libc::SYS_faccessat,
+    libc::SYS_faccessat2,
+    libc::SYS_mkdirat,
+    libc::SYS_unlinkat,
+    libc::SYS_renameat,
+    libc::SYS_renameat2,
+    libc::SYS_fchmod,
+    libc::SYS_fchmodat,
+    libc::SYS_ftruncate,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_flock,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_dup,
+    libc::SYS_dup3,
+    libc::SYS_pipe2,
+    libc::SYS_getcwd,
+    libc::SYS_umask,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mprotect,
+    libc::SYS_mremap,
+    libc::SYS_madvise,
+    libc::SYS_mincore,
+    libc::SYS_msync,
+    libc::SYS_membarrier,
+    libc::SYS_brk,
+    libc::SYS_socket,
+    libc::SYS_socketpair,
+    libc::SYS_bind,
+    libc::SYS_listen,
+    libc::SYS_accept4,
+    libc::SYS_connect,
+    libc::SYS_shutdown,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_getsockopt,
+    libc::SYS_setsockopt,
+    libc::SYS_sendto,
+    libc::SYS_recvfrom,
+    libc::SYS_sendmsg,
+    libc::SYS_recvmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_recvmmsg,
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_eventfd2,
+    libc::SYS_ppoll,
+    libc::SYS_pselect6,
+    libc::SYS_futex,
+    libc::SYS_set_robust_list,
+    libc::SYS_get_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_set_tid_address,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_sched_getparam,
+    libc::SYS_sched_getscheduler,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_rt_sigtimedwait,
+    libc::SYS_sigaltstack,
+    libc::SYS_restart_syscall,
+    libc::SYS_nanosleep,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_getres,
+    libc::SYS_gettimeofday,
+    libc::SYS_getrandom,
+    libc::SYS_uname,
+    libc::SYS_sysinfo,
+    libc::SYS_prlimit64,
+    libc::SYS_getrusage,
+    libc::SYS_prctl,
+    libc::SYS_capget,
+    libc::SYS_getpid,
+    libc::SYS_getppid,
+    libc::SYS_gettid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_getresuid,
+    libc::SYS_getresgid,
+    libc::SYS_getgroups,
+    libc::SYS_getpgid,
+    libc::SYS_setpgid,
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_execve,
+    libc::SYS_wait4,
+    libc::SYS_waitid,
+    libc::SYS_kill,
+    libc::SYS_tgkill,
+    libc::SYS_pidfd_open,
+    libc::SYS_pidfd_send_signal,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_arch_prctl,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_lstat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_access,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_readlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_pipe,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_dup2,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_epoll_wait,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_getrlimit,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_vfork,
+];
+
+/// Paths the plugin needs beyond the fixed read-only locations.
+#[derive(Debug, Default)]
+pub struct SandboxPaths {
+    /// Directories we create sockets or write state files in.
+    pub writable: Vec<PathBuf>,
+    /// Additional directories or files we read.
+    pub readable: Vec<PathBuf>,
+    /// Device nodes we and our helpers open for reading and writing.
+    pub devices: Vec<PathBuf>,
+    /// Helper programs we run, as paths or names looked up in PATH.
+    pub executable: Vec<PathBuf>,
+}
+
+fn existing(paths: impl IntoIterator<Item = PathBuf>) -> Vec<PathBuf> {
+    paths.into_iter().filter(|p| p.exists()).collect()
+}
+
+fn resolve(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return fs::canonicalize(program).ok();
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+        .and_then(|path| fs::canonicalize(path).ok())
+}
+
+// What the kernel loads to run `path`: the interpreter of a script or the dynamic loader of an
+// ELF executable (PT_INTERP). Both need the execute right as well.
+fn interpreter(path: &Path) -> Option<PathBuf> {
+    let mut header = [0u8; 64];
+    let mut file = fs::File::open(path).ok()?;
+    let len = file.read(&mut header).ok()?;
+    if let Some(script) = header[..len].strip_prefix(b"#!") {
+        let line = script.split(|b| *b == b'\n').next()?;
+        let program = String::from_utf8_lossy(line)
+            .split_whitespace()
+            .next()?
+            .to_string();
+        return fs::canonicalize(program).ok();
+    }
+
+    // 64-bit little-endian ELF only, which covers every platform the plugin runs on.
+    if len < 64 || &header[..4] != b"\x7fELF" || header[4] != 2 || header[5] != 1 {
+        return None;
+    }
+    let contents = fs::read(path).ok()?;
+    let u16_at = |at: usize| {
+        Some(u16::from_le_bytes(
+            contents.get(at..at + 2)?.try_into().ok()?,
+        ))
+    };
+    let u64_at = |at: usize| {
+        Some(u64::from_le_bytes(
+            contents.get(at..at + 8)?.try_into().ok()?,
+        ))
+    };
+    let phoff = u64_at(0x20)? as usize;
+    let phentsize = u16_at(0x36)? as usize;
+    for idx in 0..u16_at(0x38)? as usize {
+        let ph = phoff + idx * phentsize;
+        // PT_INTERP
+        if contents.get(ph..ph + 4)? == 3u32.to_le_bytes() {
+            let offset = u64_at(ph + 8)? as usize;
+            let size = u64_at(ph + 32)? as usize;
+            let raw = contents.get(offset..offset + size)?;
+            let raw = raw.strip_suffix(b"\0").unwrap_or(raw);
+            return fs::canonicalize(String::from_utf8_lossy(raw).as_ref()).ok();
+        }
+    }
+    None
+}
+
+// Helper programs together with the interpreters needed to start them.
+fn executables(programs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut out = Vec::new();
+    for program in programs {
+        let mut next = resolve(program);
+        while let Some(path) = next {
+            if out.contains(&path) {
+                break;
+            }
+            next = interpreter(&path);
+            out.push(path);
+        }
+    }
+    out
+}
+
+/// Restricts filesystem access with Landlock to the paths the plugin works with.
+///
+/// Landlock only affects the calling thread and threads it spawns later, so this must run before
+/// the tokio runtime is built. It only grants access beneath paths that exist at that point.
+pub fn restrict_filesystem(paths: &SandboxPaths) -> anyhow::Result<()> {
+    let abi = ABI::V2;
+    let read = AccessFs::ReadFile | AccessFs::ReadDir;
+
+    let read_only = existing(
+        READ_ONLY_PATHS
+            .iter()
+            .chain(LIBRARY_PATHS)
+            .map(PathBuf::from)
+            .chain(paths.readable.iter().cloned()),
+    );
+    let devices = existing(
+        DEVICE_PATHS
+            .iter()
+            .map(PathBuf::from)
+            .chain(paths.devices.iter().cloned()),
+    );
+    let executable = executables(&paths.executable);
+    let writable = existing(paths.writable.iter().cloned());
+
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))?
+        .create()?
+        .add_rules(path_beneath_rules(&read_only, read))?
+        .add_rules(path_beneath_rules(&devices, read | AccessFs::WriteFile))?
+        .add_rules(path_beneath_rules(
+            &executable,
+            AccessFs::ReadFile | AccessFs::Execute,
+        ))?
+        .add_rules(path_beneath_rules(&writable, AccessFs::from_all(abi)))?
+        .restrict_self()?;
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => println!("landlock sandbox enforced"),
+        RulesetStatus::PartiallyEnforced => {
+            println!("landlock sandbox partially enforced (older kernel ABI)")
+        }
+        RulesetStatus::NotEnforced => eprintln!(
+            "warning: landlock is not supported by this kernel, filesystem access is unrestricted"
+        ),
+    }
+    Ok(())
+}
+
+/// Limits every thread of the process, and the helpers it runs, to [`ALLOWED_SYSCALLS`]. Runs
+/// once startup is done, so that only what serving needs has to be allowed.
+pub fn restrict_syscalls() -> anyhow::Result<()> {
+    let rules: BTreeMap<i64, Vec<SeccompRule>> = ALLOWED_SYSCALLS
+        .iter()
+        .map(|&nr| (nr, Vec::new()))
+        .collect();
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Errno(libc::EPERM as u32),
+        SeccompAction::Allow,
+        TargetArch::try_from(env::consts::ARCH)?,
+    )?;
+    let program: seccompiler::BpfProgram = filter.try_into()?;
+    seccompiler::apply_filter_all_threads(&program)?;
+    println!(
+        "seccomp filter installed ({} syscalls allowed)",
+        ALLOWED_SYSCALLS.len()
+    );
+    Ok(())
+}
+
+/// Parent directory of `path`, for files that are replaced via a temporary sibling.
+pub fn parent_dir(path: &Path) -> Option<PathBuf> {
+    path.parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    const CHILD_ENV: &str = "SANDBOX_TEST_CHILD";
+
+    // Only does something when started by `helpers_run_in_sandbox`, as Landlock cannot be lifted
+    // again.
+    #[test]
+    fn run_helper_sandboxed() {
+        if env::var_os(CHILD_ENV).is_none() {
+            return;
+        }
+        restrict_filesystem(&SandboxPaths {
+            executable: vec![PathBuf::from("true")],
+            ..Default::default()
+        })
+        .unwrap();
+        let output = Command::new("true").output().unwrap();
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn helpers_run_in_sandbox() {
+        let status = Command::new(env::current_exe().unwrap())
+            .args(["--exact", "sandbox::tests::run_helper_sandboxed"])
+            .env(CHILD_ENV, "1")
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    os::unix::net::{UnixListener as StdUnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tokio::net::UnixListener;
+
+const UPGRADE_SUFFIX: &str = "-next.sock";
+
+// Listeners handed to us through socket activation, keyed by the path they are bound to.
+static INHERITED: Mutex<BTreeMap<PathBuf, StdUnixListener>> = Mutex::new(BTreeMap::new());
+
+// Finds the inode of the listening socket bound to `path` in /proc/net/unix.
+fn socket_inode(path: &Path) -> Option<u64> {
+    let table = fs::read_to_string("/proc/net/unix").ok()?;
+    let path = path.to_str()?;
+    // Num RefCount Protocol Flags Type St Inode [Path]
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(7) != Some(&path) {
+            return None;
+        }
+        fields.get(6)?.parse().ok()
+    })
+}
+
+// Finds a process holding socket `inode` open. Only processes in our PID namespace that we may
+// inspect are visible.
+fn socket_owner(inode: u64) -> Option<u32> {
+    let target = format!("socket:[{inode}]");
+    fs::read_dir("/proc").ok()?.flatten().find_map(|entry| {
+        let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+        let fds = fs::read_dir(entry.path().join("fd")).ok()?;
+        fds.flatten()
+            .any(|fd| {
+                fs::read_link(fd.path()).is_ok_and(|link| link.as_os_str() == target.as_str())
+            })
+            .then_some(pid)
+    })
+}
+
+/// Whether something is accepting connections on `path`.
+pub fn is_live(path: &Path) -> bool {
+    UnixStream::connect(path).is_ok()
+}
+
+/// Picks the socket name a new instance serves on during an upgrade: `name` if it is free,
+/// otherwise a `-next` variant, so consecutive upgrades alternate between the two.
+pub fn upgrade_socket_name(dir: &Path, name: &str) -> String {
+    if !is_live(&dir.join(name)) {
+        return name.to_string();
+    }
+    format!(
+        "{}{UPGRADE_SUFFIX}",
+        name.strip_suffix(".sock").unwrap_or(name)
+    )
+}
+
+/// Whether `path` is served through a listener inherited from the service manager.
+pub fn is_inherited(path: &Path) -> bool {
+    INHERITED.lock().unwrap().contains_key(path)
+}
+
+/// Whether `path` is a `-next` variant picked by [`upgrade_socket_name`].
+pub fn is_upgrade_socket(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.ends_with(UPGRADE_SUFFIX))
+}
+
+fn describe_owner(path: &Path) -> String {
+    match socket_inode(path).and_then(socket_owner) {
+        Some(pid) => format!("pid {pid}"),
+        None => "an unknown process".to_string(),
+    }
+}
+
+// Removes a stale socket at `path` so it can be bound again.
+//
+// If something still answers on the socket, another instance is most likely serving it and
+// unlinking the file would silently cut it off; that is refused unless `force` is set.
+fn remove_stale(path: &Path, force: bool) -> anyhow::Result<()> {
+    if fs::symlink_metadata(path).is_err() {
+        return Ok(());
+    }
+
+    if is_live(path) {
+        let owner = describe_owner(path);
+        if !force {
+            anyhow::bail!(
+                "{} is in use by {owner}; is another instance running? (use --force to take over)",
+                path.display()
+            );
+        }
+        eprintln!(
+            "warning: taking over {} from {owner} (--force)",
+            path.display()
+        );
+    } else {
+        println!("removing stale socket {}", path.display());
+    }
+
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Registers listeners inherited from the service manager so that [`bind`] hands them out instead
+/// of creating new sockets.
+pub fn adopt(listeners: Vec<StdUnixListener>) {
+    let mut inherited = INHERITED.lock().unwrap();
+    for listener in listeners {
+        let path = listener
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(Path::to_path_buf));
+        match path {
+            Some(path) => {
+                println!("using socket-activated listener for {}", path.display());
+                inherited.insert(path, listener);
+            }
+            None => eprintln!(
+                "warning: ignoring inherited file descriptor that is not a unix socket bound to a path"
+            ),
+        }
+    }
+}
+
+/// Returns a listener for `path`: the one passed in through socket activation if there is one,
+/// otherwise a freshly bound socket, replacing a stale socket file if necessary.
+///
+/// Inherited listeners are handed out as duplicates and kept, so a server restarted after a crash
+/// serves the same socket again instead of trying to replace it. Once its socket file has been
+/// removed nobody can reach an inherited listener anymore, so it is dropped for a fresh socket.
+pub fn bind(path: &Path, force: bool) -> anyhow::Result<UnixListener> {
+    {
+        let mut inherited = INHERITED.lock().unwrap();
+        if fs::symlink_metadata(path).is_err() {
+            if inherited.remove(path).is_some() {
+                println!(
+                    "socket-activated {} was removed, binding anew",
+                    path.display()
+                );
+            }
+        } else if let Some(listener) = inherited.get(path) {
+            let listener = listener.try_clone()?;
+            listener.set_nonblocking(true)?;
+            return Ok(UnixListener::from_std(listener)?);
+        }
+    }
+    remove_stale(path, force)?;
+    Ok(UnixListener::bind(path)?)
+}
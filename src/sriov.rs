@@ -0,0 +1,53 @@
+use crate::{
+    gpu::{discover_gpus, PCI_DEVICES_DIR},
+    k8s,
+    vfio::{self, vfio_device, VfioDevice},
+};
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// Lists the virtual functions created from `pf_bus_id` via its sysfs `virtfn*` links.
+fn virtual_functions(pf_bus_id: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(Path::new(PCI_DEVICES_DIR).join(pf_bus_id)) else {
+        return Vec::new();
+    };
+
+    let mut vfs: Vec<(u32, String)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let index = name.strip_prefix("virtfn")?.parse().ok()?;
+            let target = fs::read_link(entry.path()).ok()?;
+            Some((index, target.file_name()?.to_string_lossy().into_owned()))
+        })
+        .collect();
+    vfs.sort();
+    vfs.into_iter().map(|(_, bus_id)| bus_id).collect()
+}
+
+/// Discovers VFIO-bound SR-IOV virtual functions of the node's GPUs, keyed by device ID
+/// (`<resource>=<vf pci bus id>`).
+pub fn discover_vfs(
+    resource_name: &str,
+) -> (BTreeMap<String, k8s::Device>, BTreeMap<String, VfioDevice>) {
+    let mut vfs = Vec::new();
+
+    for gpu in discover_gpus() {
+        for vf_bus_id in virtual_functions(&gpu.pci_bus_id) {
+            let Some(vf) = vfio_device(&vf_bus_id) else {
+                println!(
+                    "skipping VF {vf_bus_id} of GPU {}: not bound to vfio-pci",
+                    gpu.pci_bus_id
+                );
+                continue;
+            };
+
+            vfs.push(vf);
+        }
+    }
+
+    if vfs.is_empty() {
+        eprintln!("warning: no VFIO-bound SR-IOV virtual functions found on NVIDIA GPUs");
+    }
+
+    vfio::advertise(resource_name, vfs)
+}
@@ -0,0 +1,128 @@
+use std::{
+    fmt,
+    future::Future,
+    io,
+    path::Path,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(15);
+
+/// A startup failure caused by the node still coming up rather than by our configuration.
+#[derive(Debug)]
+pub struct Transient(pub String);
+
+impl fmt::Display for Transient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Transient {}
+
+/// Reports a missing file as [`Transient`] if the node creates it while coming up: kubelet's
+/// registration socket or the driver's /proc and /dev entries. Any other missing file is a
+/// configuration error.
+pub fn missing(path: &Path, err: io::Error) -> anyhow::Error {
+    let under_dev = path
+        .strip_prefix("/dev")
+        .ok()
+        .and_then(|rest| rest.iter().next())
+        .and_then(|first| first.to_str());
+    let appears_later = path.file_name().is_some_and(|name| name == "kubelet.sock")
+        || path.starts_with("/proc/driver/nvidia")
+        || under_dev.is_some_and(|name| name.starts_with("nvidia"));
+    if err.kind() == io::ErrorKind::NotFound && appears_later {
+        return Transient(format!("{} does not exist yet", path.display())).into();
+    }
+    anyhow::Error::new(err).context(format!("cannot access {}", path.display()))
+}
+
+/// Whether `err` is worth retrying: explicitly transient, or kubelet (or another local service)
+/// not accepting connections yet.
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if cause.is::<Transient>() || cause.is::<tonic::transport::Error>() {
+            return true;
+        }
+        if let Some(status) = cause.downcast_ref::<tonic::Status>() {
+            return status.code() == tonic::Code::Unavailable;
+        }
+        cause.downcast_ref::<io::Error>().is_some_and(|err| {
+            matches!(
+                err.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::TimedOut
+            )
+        })
+    })
+}
+
+/// Time we may spend retrying transient startup failures before giving up, shared by all startup
+/// steps. Retrying internally keeps the pod out of CrashLoopBackOff during normal node bring-up,
+/// while configuration errors still fail immediately.
+pub struct RetryBudget {
+    deadline: Instant,
+}
+
+impl RetryBudget {
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            deadline: Instant::now() + budget,
+        }
+    }
+
+    /// Runs `attempt` until it succeeds, fails with a non-transient error, or the budget is spent.
+    pub async fn retry<T, F, Fut>(&self, what: &str, mut attempt: F) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let err = match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            if !is_transient(&err) {
+                return Err(err);
+            }
+            if Instant::now() + backoff > self.deadline {
+                return Err(err.context(format!("{what}: startup retry budget exhausted")));
+            }
+            eprintln!("{what} failed, retrying in {}s: {err:#}", backoff.as_secs());
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_node_paths_are_transient_when_missing() {
+        let cases = [
+            ("/var/lib/kubelet/device-plugins/kubelet.sock", true),
+            ("/proc/driver/nvidia/gpus", true),
+            ("/dev/nvidiactl", true),
+            ("/dev/nvidia-caps-imex-channels/channel0", true),
+            ("/dev/null", false),
+            ("/var/lib/nvidia-cdi-device-plugin/retirements.json", false),
+            ("/etc/cdi", false),
+        ];
+        for (path, want) in cases {
+            let err = missing(Path::new(path), io::ErrorKind::NotFound.into());
+            assert_eq!(is_transient(&err), want, "{path}");
+        }
+        let denied = missing(
+            Path::new("/dev/nvidiactl"),
+            io::ErrorKind::PermissionDenied.into(),
+        );
+        assert!(!is_transient(&denied));
+    }
+}
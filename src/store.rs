@@ -0,0 +1,247 @@
+use crate::k8s;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, OnceLock},
+};
+use tokio::sync::watch;
+
+/// Devices keyed by device ID.
+pub type Devices = BTreeMap<String, k8s::Device>;
+
+/// Why a device is reported unhealthy. Each check only adds and removes its own reason, and a
+/// device is healthy again once no reason is left, so checks cannot undo each other's verdicts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HealthReason {
+    /// Marked for retirement and waiting for its pods to finish.
+    Retiring,
+    /// Allocated through another resource name advertising the same device.
+    InUseElsewhere,
+    /// Not part of a working NVLink fabric.
+    Fabric,
+    /// The IMEX daemon is not ready.
+    Imex,
+}
+
+/// An immutable view of the devices advertised for one resource at a given generation.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    generation: u64,
+    devices: Devices,
+    // Devices without an entry have no reason to be unhealthy.
+    reasons: BTreeMap<String, BTreeSet<HealthReason>>,
+    // Built on first use and shared by every ListAndWatch stream that sends this generation.
+    response: OnceLock<k8s::ListAndWatchResponse>,
+}
+
+impl Clone for Snapshot {
+    fn clone(&self) -> Self {
+        Self {
+            generation: self.generation,
+            devices: self.devices.clone(),
+            reasons: self.reasons.clone(),
+            response: OnceLock::new(),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn devices(&self) -> &Devices {
+        &self.devices
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.devices.contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn has_reason(&self, id: &str, reason: HealthReason) -> bool {
+        self.reasons
+            .get(id)
+            .is_some_and(|reasons| reasons.contains(&reason))
+    }
+
+    pub fn reasons(&self, id: &str) -> impl Iterator<Item = HealthReason> + '_ {
+        self.reasons.get(id).into_iter().flatten().copied()
+    }
+
+    pub fn unhealthy(&self) -> usize {
+        self.devices
+            .values()
+            .filter(|dev| dev.health != "Healthy")
+            .count()
+    }
+
+    pub fn list_and_watch_response(&self) -> &k8s::ListAndWatchResponse {
+        self.response.get_or_init(|| k8s::ListAndWatchResponse {
+            devices: self.devices.values().cloned().collect(),
+        })
+    }
+}
+
+/// Devices currently advertised for one resource.
+///
+/// Readers get `Arc`-shared snapshots instead of copies of the device map, and look devices up by
+/// ID. Updates are not incremental: a connected ListAndWatch stream always holds the current
+/// snapshot, so a change copies the whole map, and the v1beta1 device plugin API has no delta
+/// updates, so every new generation is re-sent to kubelet as the full list (built once and shared
+/// by all streams). Updates therefore grow linearly with the number of devices.
+#[derive(Debug)]
+pub struct DeviceStore {
+    tx: watch::Sender<Arc<Snapshot>>,
+}
+
+impl DeviceStore {
+    pub fn new(devices: Devices) -> Self {
+        Self {
+            tx: watch::Sender::new(Arc::new(Snapshot {
+                generation: 0,
+                devices,
+                reasons: BTreeMap::new(),
+                response: OnceLock::new(),
+            })),
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.tx.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Snapshot>> {
+        self.tx.subscribe()
+    }
+
+    /// Applies `change` to the advertised devices. `change` returns whether it modified anything;
+    /// only then is a new generation published to subscribers.
+    pub fn update(&self, change: impl FnOnce(&mut Devices) -> bool) -> bool {
+        self.tx.send_if_modified(|current| {
+            let snapshot = Arc::make_mut(current);
+            if !change(&mut snapshot.devices) {
+                return false;
+            }
+            let Snapshot {
+                devices, reasons, ..
+            } = &mut *snapshot;
+            reasons.retain(|id, _| devices.contains_key(id));
+            snapshot.generation += 1;
+            snapshot.response = OnceLock::new();
+            true
+        })
+    }
+
+    /// Adds or removes `reason` for `device_id`. Returns whether its advertised health changed.
+    pub fn set_unhealthy(&self, device_id: &str, reason: HealthReason, unhealthy: bool) -> bool {
+        self.update_reasons(
+            reason,
+            |id, had| if id == device_id { unhealthy } else { had },
+        )
+    }
+
+    /// Sets `reason` on exactly the devices for which `applies` returns true. Returns whether any
+    /// device's advertised health changed.
+    pub fn set_unhealthy_where(
+        &self,
+        reason: HealthReason,
+        applies: impl Fn(&str) -> bool,
+    ) -> bool {
+        self.update_reasons(reason, |id, _| applies(id))
+    }
+
+    // `verdict` gets each device ID and whether it currently has `reason`, and returns whether it
+    // should.
+    fn update_reasons(&self, reason: HealthReason, verdict: impl Fn(&str, bool) -> bool) -> bool {
+        self.tx.send_if_modified(|current| {
+            // Checked first so that a periodic check confirming the current state copies nothing.
+            let unchanged = current.devices.keys().all(|id| {
+                let had = current.has_reason(id, reason);
+                verdict(id, had) == had
+            });
+            if unchanged {
+                return false;
+            }
+
+            let snapshot = Arc::make_mut(current);
+            let mut health_changed = false;
+            let Snapshot {
+                devices, reasons, ..
+            } = &mut *snapshot;
+            for (id, dev) in devices.iter_mut() {
+                let set = reasons.entry(id.clone()).or_default();
+                if verdict(id, set.contains(&reason)) {
+                    set.insert(reason);
+                } else {
+                    set.remove(&reason);
+                }
+                let health = if set.is_empty() {
+                    "Healthy"
+                } else {
+                    "Unhealthy"
+                };
+                if dev.health != health {
+                    dev.health = health.to_string();
+                    health_changed = true;
+                }
+            }
+            reasons.retain(|_, set| !set.is_empty());
+
+            // Reasons alone are not sent to kubelet, so they don't make a new generation.
+            if health_changed {
+                snapshot.generation += 1;
+                snapshot.response = OnceLock::new();
+            }
+            health_changed
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(ids: &[&str]) -> DeviceStore {
+        DeviceStore::new(
+            ids.iter()
+                .map(|id| {
+                    let dev = k8s::Device {
+                        id: id.to_string(),
+                        health: "Healthy".to_string(),
+                        topology: None,
+                    };
+                    (id.to_string(), dev)
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn healthy_only_without_reasons() {
+        let store = store(&["gpu=0", "gpu=1"]);
+        let healthy = |id: &str| store.snapshot().devices()[id].health == "Healthy";
+
+        assert!(store.set_unhealthy("gpu=0", HealthReason::Retiring, true));
+        assert_eq!((healthy("gpu=0"), healthy("gpu=1")), (false, true));
+
+        assert!(store.set_unhealthy_where(HealthReason::Fabric, |_| true));
+        assert_eq!((healthy("gpu=0"), healthy("gpu=1")), (false, false));
+
+        // The fabric recovering must not undo the retirement.
+        let generation = store.snapshot().generation();
+        assert!(store.set_unhealthy_where(HealthReason::Fabric, |_| false));
+        assert_eq!((healthy("gpu=0"), healthy("gpu=1")), (false, true));
+        assert!(store.snapshot().has_reason("gpu=0", HealthReason::Retiring));
+        assert_eq!(store.snapshot().generation(), generation + 1);
+
+        // Confirming the current state publishes nothing.
+        assert!(!store.set_unhealthy_where(HealthReason::Fabric, |_| false));
+        assert_eq!(store.snapshot().generation(), generation + 1);
+
+        assert!(store.set_unhealthy("gpu=0", HealthReason::Retiring, false));
+        assert_eq!(store.snapshot().unhealthy(), 0);
+    }
+}
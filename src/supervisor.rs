@@ -0,0 +1,111 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+    task::{JoinError, JoinHandle},
+    time::sleep,
+};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Consecutive failures after which a subsystem is considered broken and the process exits.
+const MAX_FAILURES: u32 = 5;
+/// A subsystem that ran this long before failing starts over with a clean failure count.
+const STABLE_AFTER: Duration = Duration::from_secs(300);
+
+// Aborts the supervised task when the supervisor itself is aborted or dropped.
+struct Child(JoinHandle<()>);
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+fn panic_message(err: JoinError) -> String {
+    let payload = err.into_panic();
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// Restarts background subsystems that panic or exit before shutdown, with exponential backoff.
+/// A subsystem that keeps failing is reported on the channel returned by [`Supervisor::new`] so
+/// the process can exit instead of running on without it.
+#[derive(Clone)]
+pub struct Supervisor {
+    shutdown: watch::Receiver<bool>,
+    failed: mpsc::Sender<String>,
+}
+
+impl Supervisor {
+    pub fn new(shutdown: watch::Receiver<bool>) -> (Self, mpsc::Receiver<String>) {
+        let (failed, failed_rx) = mpsc::channel(1);
+        (Self { shutdown, failed }, failed_rx)
+    }
+
+    /// Runs the task returned by `start` under supervision. `start` is called again whenever the
+    /// task panics, exits while we are not shutting down, or fails to start.
+    pub fn spawn<F, Fut>(&self, name: impl Into<String>, mut start: F) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<JoinHandle<()>>> + Send + 'static,
+    {
+        let name = name.into();
+        let mut shutdown = self.shutdown.clone();
+        let failed = self.failed.clone();
+
+        tokio::spawn(async move {
+            let mut failures = 0;
+            let mut backoff = INITIAL_BACKOFF;
+            loop {
+                let started = Instant::now();
+                let reason = match start().await {
+                    Err(err) => format!("failed to start: {err}"),
+                    Ok(handle) => {
+                        let mut child = Child(handle);
+                        match (&mut child.0).await {
+                            Ok(()) if *shutdown.borrow() => return,
+                            Ok(()) => "exited unexpectedly".to_string(),
+                            Err(err) if err.is_cancelled() => return,
+                            Err(err) => format!("panicked: {}", panic_message(err)),
+                        }
+                    }
+                };
+                if *shutdown.borrow() {
+                    return;
+                }
+
+                if started.elapsed() >= STABLE_AFTER {
+                    failures = 0;
+                    backoff = INITIAL_BACKOFF;
+                }
+                failures += 1;
+                eprintln!("{name} {reason} (failure {failures}/{MAX_FAILURES})");
+                if failures >= MAX_FAILURES {
+                    eprintln!("{name} keeps failing, giving up");
+                    let _ = failed.send(name).await;
+                    return;
+                }
+
+                println!("restarting {name} in {}s", backoff.as_secs());
+                select! {
+                    _ = sleep(backoff) => {},
+                    changed = shutdown.changed() => {
+                        if changed.is_err() || *shutdown.borrow() {
+                            return;
+                        }
+                    }
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        })
+    }
+}
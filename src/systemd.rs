@@ -0,0 +1,100 @@
+use std::{
+    env,
+    os::{
+        fd::{FromRawFd, RawFd},
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram, UnixListener},
+    },
+    time::Duration,
+};
+use tokio::{select, sync::watch, task::JoinHandle, time::interval};
+
+// First file descriptor passed by socket activation (SD_LISTEN_FDS_START).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Takes the listeners passed in through socket activation (`LISTEN_FDS`).
+///
+/// Must run before other threads start: it clears the activation variables so they are not passed
+/// on to hooks and helpers we spawn.
+pub fn listen_fds() -> Vec<UnixListener> {
+    let pid = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok());
+    let count: RawFd = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0);
+    unsafe {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+    if pid != Some(std::process::id()) {
+        return Vec::new();
+    }
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            UnixListener::from_raw_fd(fd)
+        })
+        .collect()
+}
+
+fn notify_addr() -> Option<SocketAddr> {
+    let path = env::var_os("NOTIFY_SOCKET")?;
+    let path = path.to_str()?;
+    // A leading '@' denotes a socket in the abstract namespace.
+    match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name).ok(),
+        None => SocketAddr::from_pathname(path).ok(),
+    }
+}
+
+/// Sends `state` (e.g. `READY=1`) to the service manager. Does nothing when we were not started
+/// by systemd with `Type=notify`.
+pub fn notify(state: &str) {
+    let Some(addr) = notify_addr() else {
+        return;
+    };
+    let result =
+        UnixDatagram::unbound().and_then(|sock| sock.send_to_addr(state.as_bytes(), &addr));
+    if let Err(err) = result {
+        eprintln!("failed to notify systemd ({state}): {err}");
+    }
+}
+
+// Half of the watchdog timeout systemd expects us to ping within, if the watchdog is enabled for
+// this process.
+fn watchdog_interval() -> Option<Duration> {
+    if let Some(pid) = env::var_os("WATCHDOG_PID")
+        && pid.to_str()?.parse::<u32>().ok()? != std::process::id()
+    {
+        return None;
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Pings the systemd watchdog with `WATCHDOG=1` until shutdown. Returns `None` when systemd did
+/// not enable the watchdog for us.
+pub fn spawn_watchdog(mut shutdown: watch::Receiver<bool>) -> Option<JoinHandle<()>> {
+    let period = watchdog_interval()?;
+    println!(
+        "systemd watchdog enabled, pinging every {}ms",
+        period.as_millis()
+    );
+    Some(tokio::spawn(async move {
+        let mut ticks = interval(period);
+        loop {
+            select! {
+                _ = ticks.tick() => notify("WATCHDOG=1"),
+                changed = shutdown.changed() => {
+                    if changed.is_err() || *shutdown.borrow() {
+                        break;
+                    }
+                }
+            }
+        }
+    }))
+}
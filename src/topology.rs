@@ -0,0 +1,216 @@
+use crate::gpu::{normalize_bus_id, PCI_DEVICES_DIR};
+use std::{collections::BTreeMap, fmt, fs, path::Path, process::Command};
+
+/// How two GPUs are connected, using the vocabulary of `nvidia-smi topo -m`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Link {
+    /// the GPU itself
+    Own,
+    /// bonded NVLinks
+    NvLink(u32),
+    /// at most a single PCIe switch
+    Pix,
+    /// multiple PCIe bridges, without crossing the host bridge
+    Pxb,
+    /// a PCIe host bridge
+    Phb,
+    /// host bridges within one NUMA node
+    Node,
+    /// the interconnect between NUMA nodes
+    Sys,
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Link::Own => f.write_str("X"),
+            Link::NvLink(count) => write!(f, "NV{count}"),
+            Link::Pix => f.write_str("PIX"),
+            Link::Pxb => f.write_str("PXB"),
+            Link::Phb => f.write_str("PHB"),
+            Link::Node => f.write_str("NODE"),
+            Link::Sys => f.write_str("SYS"),
+        }
+    }
+}
+
+pub const LEGEND: &str = "\
+  X    = Self
+  SYS  = Connection traversing PCIe as well as the interconnect between NUMA nodes
+  NODE = Connection traversing PCIe as well as the interconnect between host bridges within a NUMA node
+  PHB  = Connection traversing PCIe as well as a PCIe host bridge
+  PXB  = Connection traversing multiple PCIe bridges (without traversing the PCIe host bridge)
+  PIX  = Connection traversing at most a single PCIe bridge
+  NV#  = Connection traversing a bonded set of # NVLinks";
+
+type PciPath = (String, Vec<String>);
+
+// The PCI host bridge a device sits under and the bridges between it and the device, from the
+// device's path in sysfs (e.g. /sys/devices/pci0000:00/0000:00:01.0/0000:01:00.0).
+fn split_pci_path(path: &Path) -> Option<PciPath> {
+    let mut components = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .skip_while(|c| !c.starts_with("pci"));
+    let host_bridge = components.next()?;
+    let mut bridges: Vec<String> = components.collect();
+    bridges.pop();
+    Some((host_bridge, bridges))
+}
+
+fn pci_path(pci_bus_id: &str) -> Option<PciPath> {
+    split_pci_path(&fs::canonicalize(Path::new(PCI_DEVICES_DIR).join(pci_bus_id)).ok()?)
+}
+
+fn across_host_bridges(numa_a: Option<i64>, numa_b: Option<i64>) -> Link {
+    if numa_a == numa_b {
+        Link::Node
+    } else {
+        Link::Sys
+    }
+}
+
+/// Classifies the PCIe path between two GPUs from sysfs. A PCIe switch shows up as an upstream
+/// port with one downstream port per device, so devices whose paths part below the root port
+/// with at most one more bridge each count as behind a single switch.
+pub fn pcie_link(a: &str, b: &str, numa_a: Option<i64>, numa_b: Option<i64>) -> Link {
+    classify(pci_path(a), pci_path(b), numa_a, numa_b)
+}
+
+fn classify(
+    a: Option<PciPath>,
+    b: Option<PciPath>,
+    numa_a: Option<i64>,
+    numa_b: Option<i64>,
+) -> Link {
+    let (Some((host_a, bridges_a)), Some((host_b, bridges_b))) = (a, b) else {
+        return across_host_bridges(numa_a, numa_b);
+    };
+    if host_a != host_b {
+        return across_host_bridges(numa_a, numa_b);
+    }
+
+    let common = bridges_a
+        .iter()
+        .zip(&bridges_b)
+        .take_while(|(a, b)| a == b)
+        .count();
+    match common {
+        0 => Link::Phb,
+        1 => Link::Pxb,
+        _ if bridges_a.len() - common <= 1 && bridges_b.len() - common <= 1 => Link::Pix,
+        _ => Link::Pxb,
+    }
+}
+
+/// NVLink connections between GPUs, keyed by pairs of PCI bus IDs, as reported by
+/// `nvidia-smi topo -m`. Empty if nvidia-smi is unavailable.
+pub fn nvlinks() -> BTreeMap<(String, String), u32> {
+    let Some(bus_ids) = smi_bus_ids() else {
+        return BTreeMap::new();
+    };
+    let Ok(output) = Command::new("nvidia-smi").args(["topo", "-m"]).output() else {
+        return BTreeMap::new();
+    };
+    let text = String::from_utf8_lossy(&output.stdout);
+
+    let mut links = BTreeMap::new();
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(row) = fields
+            .next()
+            .and_then(|label| label.strip_prefix("GPU")?.parse::<usize>().ok())
+        else {
+            continue;
+        };
+        for (col, field) in fields.take(bus_ids.len()).enumerate() {
+            if let Some(count) = field.strip_prefix("NV").and_then(|n| n.parse().ok())
+                && let (Some(a), Some(b)) = (bus_ids.get(row), bus_ids.get(col))
+            {
+                links.insert((a.clone(), b.clone()), count);
+            }
+        }
+    }
+    links
+}
+
+// PCI bus IDs in nvidia-smi's GPU index order.
+fn smi_bus_ids() -> Option<Vec<String>> {
+    let output = Command::new("nvidia-smi")
+        .args(["--query-gpu=pci.bus_id", "--format=csv,noheader"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(normalize_bus_id)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_pcie_paths() {
+        let cases = [
+            // Two downstream ports of one switch below the same root port.
+            (
+                "/sys/devices/pci0000:00/0000:00:01.0/0000:01:00.0/0000:02:00.0/0000:03:00.0",
+                "/sys/devices/pci0000:00/0000:00:01.0/0000:01:00.0/0000:02:01.0/0000:04:00.0",
+                Some(0),
+                Some(0),
+                Link::Pix,
+            ),
+            // Separate switches below one root port.
+            (
+                "/sys/devices/pci0000:00/0000:00:01.0/0000:01:00.0/0000:02:00.0/0000:03:00.0/0000:04:00.0/0000:05:00.0",
+                "/sys/devices/pci0000:00/0000:00:01.0/0000:01:00.0/0000:02:01.0/0000:06:00.0/0000:07:00.0/0000:08:00.0",
+                Some(0),
+                Some(0),
+                Link::Pxb,
+            ),
+            // Different root ports of one host bridge.
+            (
+                "/sys/devices/pci0000:00/0000:00:01.0/0000:01:00.0",
+                "/sys/devices/pci0000:00/0000:00:02.0/0000:02:00.0",
+                Some(0),
+                Some(0),
+                Link::Phb,
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:01.0/0000:01:00.0",
+                "/sys/devices/pci0000:40/0000:40:01.0/0000:41:00.0",
+                Some(0),
+                Some(0),
+                Link::Node,
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:01.0/0000:01:00.0",
+                "/sys/devices/pci0000:80/0000:80:01.0/0000:81:00.0",
+                Some(0),
+                Some(1),
+                Link::Sys,
+            ),
+        ];
+        for (a, b, numa_a, numa_b, want) in cases {
+            let link = classify(
+                split_pci_path(Path::new(a)),
+                split_pci_path(Path::new(b)),
+                numa_a,
+                numa_b,
+            );
+            assert_eq!(link, want, "{a} <-> {b}");
+        }
+    }
+
+    #[test]
+    fn falls_back_to_numa_without_sysfs() {
+        assert_eq!(classify(None, None, Some(0), Some(0)), Link::Node);
+        assert_eq!(classify(None, None, Some(0), None), Link::Sys);
+    }
+}
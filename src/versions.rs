@@ -0,0 +1,159 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const DRIVER_VERSION_PATH: &str = "/proc/driver/nvidia/version";
+pub const DEFAULT_CDI_SPEC_DIRS: &[&str] = &["/etc/cdi", "/var/run/cdi"];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionInfo {
+    pub driver: Option<String>,
+    pub cuda: Option<String>,
+    pub cdi_spec: Option<String>,
+    pub plugin: String,
+}
+
+pub fn detect_versions(resource_name: &str, cdi_spec_dirs: &[PathBuf]) -> VersionInfo {
+    VersionInfo {
+        driver: detect_driver_version(),
+        cuda: detect_cuda_version(),
+        cdi_spec: detect_cdi_spec_version(resource_name, cdi_spec_dirs),
+        plugin: env!("CARGO_PKG_VERSION").to_string(),
+    }
+}
+
+pub fn detect_driver_version() -> Option<String> {
+    let contents = fs::read_to_string(DRIVER_VERSION_PATH).ok()?;
+    parse_driver_version(&contents)
+}
+
+// e.g. "NVRM version: NVIDIA UNIX x86_64 Kernel Module  550.54.14  Thu Feb 22 01:44:30 UTC 2024"
+fn parse_driver_version(contents: &str) -> Option<String> {
+    let line = contents.lines().find(|l| l.starts_with("NVRM version:"))?;
+    line.split_whitespace()
+        .find(|tok| tok.contains('.') && tok.chars().all(|c| c.is_ascii_digit() || c == '.'))
+        .map(str::to_string)
+}
+
+// The CUDA version supported by the driver is only reported by libcuda, so ask nvidia-smi
+// rather than linking against it.
+pub fn detect_cuda_version() -> Option<String> {
+    let output = Command::new("nvidia-smi").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (_, rest) = stdout.split_once("CUDA Version:")?;
+    rest.split_whitespace()
+        .next()
+        .map(|v| v.trim_end_matches('|').to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Returns the `cdiVersion` of the first spec in `cdi_spec_dirs` whose kind matches `resource_name`.
+pub fn detect_cdi_spec_version(resource_name: &str, cdi_spec_dirs: &[PathBuf]) -> Option<String> {
+    for dir in cdi_spec_dirs {
+        for path in cdi_spec_files(dir) {
+            let Ok(contents) = fs::read_to_string(&path) else {
+                continue;
+            };
+            if spec_field(&contents, "kind").as_deref() != Some(resource_name) {
+                continue;
+            }
+            if let Some(version) = spec_field(&contents, "cdiVersion") {
+                return Some(version);
+            }
+        }
+    }
+    None
+}
+
+pub fn cdi_spec_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            matches!(
+                p.extension().and_then(|e| e.to_str()),
+                Some("yaml" | "yml" | "json")
+            )
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+// CDI specs are either JSON or YAML. Only top-level scalars are needed here, and those sit on
+// their own unindented line in YAML, so avoid pulling in a YAML parser.
+fn spec_field(contents: &str, field: &str) -> Option<String> {
+    if contents.trim_start().starts_with('{') {
+        let spec: serde_json::Value = serde_json::from_str(contents).ok()?;
+        return spec.get(field)?.as_str().map(str::to_string);
+    }
+
+    contents.lines().find_map(|line| {
+        let value = line.strip_prefix(field)?.trim_start().strip_prefix(':')?;
+        let value = value
+            .split(" #")
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'');
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_driver_version() {
+        let cases = [
+            (
+                "NVRM version: NVIDIA UNIX x86_64 Kernel Module  550.54.14  Thu Feb 22 01:44:30 UTC 2024\nGCC version:  gcc version 12.2.0\n",
+                Some("550.54.14"),
+            ),
+            (
+                "NVRM version: NVIDIA UNIX Open Kernel Module for x86_64  570.86.15  Release Build\n",
+                Some("570.86.15"),
+            ),
+            ("GCC version:  gcc version 12.2.0\n", None),
+            ("", None),
+        ];
+        for (contents, want) in cases {
+            assert_eq!(
+                parse_driver_version(contents).as_deref(),
+                want,
+                "{contents:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn reads_top_level_spec_fields() {
+        let yaml = "---\ncdiVersion: \"0.5.0\"\nkind: nvidia.com/gpu # generated\ndevices:\n  - name: \"0\"\n    kind: nested\n";
+        let json = r#"{"cdiVersion":"0.6.0","kind":"nvidia.com/gpu","devices":[]}"#;
+        let cases = [
+            (yaml, "cdiVersion", Some("0.5.0")),
+            (yaml, "kind", Some("nvidia.com/gpu")),
+            (yaml, "name", None),
+            (json, "cdiVersion", Some("0.6.0")),
+            (json, "kind", Some("nvidia.com/gpu")),
+            (json, "devices", None),
+            ("kind:\n", "kind", None),
+        ];
+        for (contents, field, want) in cases {
+            assert_eq!(
+                spec_field(contents, field).as_deref(),
+                want,
+                "{field} in {contents:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{
+    gpu::{pci_numa_node, PCI_DEVICES_DIR},
+    k8s, topology_info,
+};
+use std::{collections::BTreeMap, fs, path::Path};
+
+const VFIO_DEV_DIR: &str = "/dev/vfio";
+const VFIO_CONTAINER_DEVICE: &str = "/dev/vfio/vfio";
+const VFIO_PCI_DRIVER: &str = "vfio-pci";
+const NVIDIA_VENDOR_ID: &str = "0x10de";
+// VGA and 3D controller display classes; NVSwitches and audio functions are excluded.
+const GPU_CLASS_PREFIXES: &[&str] = &["0x0300", "0x0302"];
+
+/// A PCI function bound to vfio-pci, passed through as its IOMMU group device node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VfioDevice {
+    pub pci_bus_id: String,
+    pub iommu_group: String,
+    pub numa_node: Option<i64>,
+}
+
+fn link_name(path: &Path) -> Option<String> {
+    let target = fs::read_link(path).ok()?;
+    Some(target.file_name()?.to_string_lossy().into_owned())
+}
+
+pub fn pci_driver(pci_bus_id: &str) -> Option<String> {
+    link_name(&Path::new(PCI_DEVICES_DIR).join(pci_bus_id).join("driver"))
+}
+
+/// Returns the VFIO view of `pci_bus_id`, or `None` if it is not bound to vfio-pci.
+pub fn vfio_device(pci_bus_id: &str) -> Option<VfioDevice> {
+    if pci_driver(pci_bus_id).as_deref() != Some(VFIO_PCI_DRIVER) {
+        return None;
+    }
+    let iommu_group = link_name(
+        &Path::new(PCI_DEVICES_DIR)
+            .join(pci_bus_id)
+            .join("iommu_group"),
+    )?;
+    Some(VfioDevice {
+        pci_bus_id: pci_bus_id.to_string(),
+        iommu_group,
+        numa_node: pci_numa_node(pci_bus_id),
+    })
+}
+
+/// Keys `devices` by device ID (`<resource>=<pci bus id>`) for advertisement, alongside the
+/// lookup used at allocation time.
+pub fn advertise(
+    resource_name: &str,
+    devices: Vec<VfioDevice>,
+) -> (BTreeMap<String, k8s::Device>, BTreeMap<String, VfioDevice>) {
+    let mut devs = BTreeMap::new();
+    let mut vfio = BTreeMap::new();
+
+    for dev in devices {
+        let id = format!("{resource_name}={}", dev.pci_bus_id);
+        devs.insert(
+            id.clone(),
+            k8s::Device {
+                id: id.clone(),
+                health: "Healthy".to_string(),
+                topology: topology_info(dev.numa_node),
+            },
+        );
+        vfio.insert(id, dev);
+    }
+
+    (devs, vfio)
+}
+
+/// Discovers NVIDIA GPUs bound to vfio-pci for passthrough into VM-based runtimes such as Kata.
+/// SR-IOV virtual functions are left to the separate VF resource.
+pub fn discover_vfio_gpus() -> Vec<VfioDevice> {
+    let Ok(entries) = fs::read_dir(PCI_DEVICES_DIR) else {
+        return Vec::new();
+    };
+
+    let mut devs: Vec<VfioDevice> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let vendor = fs::read_to_string(path.join("vendor")).ok()?;
+            let class = fs::read_to_string(path.join("class")).ok()?;
+            if vendor.trim() != NVIDIA_VENDOR_ID
+                || !GPU_CLASS_PREFIXES
+                    .iter()
+                    .any(|p| class.trim().starts_with(p))
+                || path.join("physfn").exists()
+            {
+                return None;
+            }
+            vfio_device(&entry.file_name().to_string_lossy())
+        })
+        .collect();
+    devs.sort_by(|a, b| a.pci_bus_id.cmp(&b.pci_bus_id));
+
+    if devs.is_empty() {
+        eprintln!("warning: no NVIDIA GPUs bound to {VFIO_PCI_DRIVER} found");
+    }
+
+    devs
+}
+
+/// Device nodes needed to hand `devices` to a VMM: the VFIO container plus one node per IOMMU
+/// group.
+pub fn device_specs<'a>(devices: impl IntoIterator<Item = &'a VfioDevice>) -> Vec<k8s::DeviceSpec> {
+    let mut paths = vec![VFIO_CONTAINER_DEVICE.to_string()];
+    for dev in devices {
+        let path = format!("{VFIO_DEV_DIR}/{}", dev.iommu_group);
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    paths
+        .into_iter()
+        .map(|path| k8s::DeviceSpec {
+            container_path: path.clone(),
+            host_path: path,
+            permissions: "rw".to_string(),
+        })
+        .collect()
+}
+
+/// Name of the env var KubeVirt and similar VMMs read to find the PCI addresses allocated for
+/// `resource_name`, e.g. `PCI_RESOURCE_NVIDIA_COM_GPU_VF`.
+pub fn pci_resource_env(resource_name: &str) -> String {
+    let suffix: String = resource_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("PCI_RESOURCE_{suffix}")
+}